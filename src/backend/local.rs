@@ -0,0 +1,152 @@
+//! Local filesystem backend: each key maps to a file below the repository root.
+
+use super::{Backend, BackendResult, ObjectInfo};
+use crate::error::BackendError;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use walkdir::WalkDir;
+
+/// Stores objects as plain files under `root`, creating directories on demand.
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut path = self.root.clone();
+        path.extend(key.split('/').filter(|c| !c.is_empty()));
+        path
+    }
+}
+
+fn io_error(key: &str, source: std::io::Error) -> BackendError {
+    if source.kind() == std::io::ErrorKind::NotFound {
+        BackendError::NotFound(key.to_string())
+    } else {
+        BackendError::Io {
+            key: key.to_string(),
+            source,
+        }
+    }
+}
+
+impl Backend for LocalBackend {
+    fn location(&self) -> String {
+        self.root.display().to_string()
+    }
+
+    fn list(&self, prefix: &str) -> BackendResult<Vec<ObjectInfo>> {
+        // Walk from the deepest directory named by the prefix
+        let dir_part = prefix.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let start = self.path(dir_part);
+        if !start.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut objects = Vec::new();
+        for entry in WalkDir::new(&start) {
+            let entry = entry.map_err(|e| io_error(prefix, e.into()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(&self.root)
+                .unwrap_or(entry.path());
+            let key = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if key.starts_with(prefix) {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                objects.push(ObjectInfo { key, size });
+            }
+        }
+
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    fn read(&self, key: &str) -> BackendResult<Vec<u8>> {
+        std::fs::read(self.path(key)).map_err(|e| io_error(key, e))
+    }
+
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> BackendResult<Vec<u8>> {
+        let mut file = std::fs::File::open(self.path(key)).map_err(|e| io_error(key, e))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| io_error(key, e))?;
+        let mut buf = Vec::new();
+        file.take(length)
+            .read_to_end(&mut buf)
+            .map_err(|e| io_error(key, e))?;
+        Ok(buf)
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> BackendResult<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| io_error(key, e))?;
+        }
+        std::fs::write(&path, data).map_err(|e| io_error(key, e))
+    }
+
+    fn delete(&self, key: &str) -> BackendResult<()> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error(key, e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn exists(&self, key: &str) -> BackendResult<bool> {
+        Ok(self.path(key).is_file())
+    }
+
+    fn size(&self, key: &str) -> BackendResult<Option<u64>> {
+        match std::fs::metadata(self.path(key)) {
+            Ok(m) => Ok(Some(m.len())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error(key, e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_list() {
+        let root = std::env::temp_dir().join("but-next-test-local-backend");
+        let _ = std::fs::remove_dir_all(&root);
+        let backend = LocalBackend::new(root.clone());
+
+        backend.write("blobs/ab/cdef", b"blob").unwrap();
+        backend.write("snapshots/one.json", b"{}").unwrap();
+        assert!(backend.exists("blobs/ab/cdef").unwrap());
+        assert_eq!(backend.read("blobs/ab/cdef").unwrap(), b"blob");
+        assert_eq!(backend.read_range("blobs/ab/cdef", 1, 2).unwrap(), b"lo");
+        assert_eq!(backend.size("snapshots/one.json").unwrap(), Some(2));
+
+        let keys: Vec<_> = backend
+            .list("blobs/")
+            .unwrap()
+            .into_iter()
+            .map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["blobs/ab/cdef"]);
+
+        backend.delete("blobs/ab/cdef").unwrap();
+        backend.delete("blobs/ab/cdef").unwrap();
+        assert!(matches!(
+            backend.read("blobs/ab/cdef"),
+            Err(BackendError::NotFound(_))
+        ));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
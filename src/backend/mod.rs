@@ -0,0 +1,220 @@
+//! # Storage Backends
+//!
+//! All repository I/O goes through the [`Backend`] trait, which models the
+//! repository as a flat namespace of `/`-separated keys (`snapshots/<id>.json`,
+//! `blobs/a1/b2c3...`). The manifest layer decides *what* is stored under which
+//! key; a backend only decides *where* the bytes live.
+//!
+//! The backend is selected by the `repository` URL in `[settings]`:
+//!
+//! | URL                         | Backend                  |
+//! |-----------------------------|--------------------------|
+//! | `/srv/backup`, `local:/srv` | [`local::LocalBackend`]  |
+//! | `file:///srv/backup`        | [`local::LocalBackend`]  |
+//! | `s3:https://host/bucket/p`  | [`s3::S3Backend`]        |
+//! | `rest:https://host:8000/`   | [`rest::RestBackend`]    |
+//! | `exec:/usr/bin/helper`      | [`process::ProcessBackend`] |
+//!
+//! Without a `repository` URL, `repo_path` is used as a local repository.
+
+pub mod local;
+pub mod process;
+pub mod rest;
+pub mod s3;
+
+use crate::config::Settings;
+use crate::error::BackendError;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub use local::LocalBackend;
+
+/// Result type for backend operations.
+pub type BackendResult<T> = std::result::Result<T, BackendError>;
+
+/// An object stored in a backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectInfo {
+    /// Full key, e.g. `snapshots/20240101-120000-documents.json`.
+    pub key: String,
+
+    /// Size of the stored object in bytes.
+    pub size: u64,
+}
+
+/// Storage operations needed by the repository.
+///
+/// Keys always use `/` as separator regardless of platform. Writes replace
+/// existing objects; deleting a missing key is not an error.
+pub trait Backend: Send + Sync {
+    /// Human-readable location of the repository, for display.
+    fn location(&self) -> String;
+
+    /// Lists all objects whose key starts with `prefix`, recursively.
+    fn list(&self, prefix: &str) -> BackendResult<Vec<ObjectInfo>>;
+
+    /// Reads a whole object.
+    fn read(&self, key: &str) -> BackendResult<Vec<u8>>;
+
+    /// Reads `length` bytes starting at `offset`. Short reads at the end of
+    /// the object return fewer bytes.
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> BackendResult<Vec<u8>>;
+
+    /// Writes an object, replacing any existing object with the same key.
+    fn write(&self, key: &str, data: &[u8]) -> BackendResult<()>;
+
+    /// Deletes an object.
+    fn delete(&self, key: &str) -> BackendResult<()>;
+
+    /// Returns true if an object exists under `key`.
+    fn exists(&self, key: &str) -> BackendResult<bool>;
+
+    /// Returns the stored size of an object, or `None` if it does not exist.
+    fn size(&self, key: &str) -> BackendResult<Option<u64>>;
+}
+
+/// Opens the repository configured in `settings`.
+pub fn open(settings: &Settings) -> BackendResult<Box<dyn Backend>> {
+    match &settings.repository {
+        Some(url) => open_url(url, settings),
+        None => Ok(Box::new(LocalBackend::new(settings.repo_path.clone()))),
+    }
+}
+
+/// Opens a repository from a URL such as `local:/srv/backup`, taking
+/// backend-specific options from `settings`.
+pub fn open_url(url: &str, settings: &Settings) -> BackendResult<Box<dyn Backend>> {
+    let (scheme, rest) = split_scheme(url);
+    match scheme {
+        None => Ok(Box::new(LocalBackend::new(url.into()))),
+        Some("local") => Ok(Box::new(LocalBackend::new(rest.into()))),
+        Some("file") => Ok(Box::new(LocalBackend::new(
+            rest.strip_prefix("//").unwrap_or(rest).into(),
+        ))),
+        Some("exec") => Ok(Box::new(process::ProcessBackend::open(
+            rest,
+            &settings.exec.clone().unwrap_or_default(),
+        )?)),
+        Some("rest") => Ok(Box::new(rest::RestBackend::open(rest)?)),
+        Some("s3") => Ok(Box::new(s3::S3Backend::open(
+            rest,
+            &settings.s3.clone().unwrap_or_default(),
+        )?)),
+        Some(other) => Err(BackendError::UnsupportedScheme(other.to_string())),
+    }
+}
+
+/// Splits `scheme:rest`. Single-letter schemes are treated as Windows drive
+/// letters (`C:\backup`) and yield no scheme.
+fn split_scheme(url: &str) -> (Option<&str>, &str) {
+    match url.split_once(':') {
+        Some((scheme, rest))
+            if scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-') =>
+        {
+            (Some(scheme), rest)
+        }
+        _ => (None, url),
+    }
+}
+
+// ─── HTTP helpers for the s3 and rest backends ──────────────────────────────
+
+/// Attempts per request before giving up, including the first one.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry; doubled on each subsequent attempt.
+const BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Calls `send` until it succeeds, retrying rate limiting (429), server
+/// errors (5xx) and transport failures with exponential backoff.
+fn send_with_retry(
+    mut send: impl FnMut() -> Result<ureq::Response, Box<ureq::Error>>,
+) -> Result<ureq::Response, Box<ureq::Error>> {
+    let mut attempt = 0;
+    loop {
+        let error = match send() {
+            Ok(resp) => return Ok(resp),
+            Err(e) => e,
+        };
+
+        let retryable = match error.as_ref() {
+            ureq::Error::Status(code, _) => *code == 429 || *code >= 500,
+            ureq::Error::Transport(_) => true,
+        };
+        attempt += 1;
+        if !retryable || attempt >= MAX_ATTEMPTS {
+            return Err(error);
+        }
+        std::thread::sleep(BASE_RETRY_DELAY * 2u32.pow(attempt - 1));
+    }
+}
+
+/// Percent-encodes per the SigV4 rules: everything except unreserved
+/// characters, and `/` unless `encode_slash` is set.
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+/// Reverses percent-encoding; `None` for malformed escapes or non-UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheme_parsing() {
+        assert_eq!(split_scheme("local:/srv"), (Some("local"), "/srv"));
+        assert_eq!(split_scheme("/srv/backup"), (None, "/srv/backup"));
+        assert_eq!(split_scheme("C:\\backup"), (None, "C:\\backup"));
+        assert_eq!(split_scheme("file:///srv"), (Some("file"), "///srv"));
+    }
+
+    fn settings() -> Settings {
+        toml::from_str::<crate::config::Config>("[settings]\n[backup.t]\nfrom = \"/\"\n")
+            .unwrap()
+            .settings
+    }
+
+    #[test]
+    fn file_url_opens_local() {
+        let backend = open_url("file:///tmp/but-next-url", &settings()).unwrap();
+        assert_eq!(backend.location(), "/tmp/but-next-url");
+    }
+
+    #[test]
+    fn unknown_scheme_rejected() {
+        assert!(matches!(
+            open_url("ftp://example.com/repo", &settings()),
+            Err(BackendError::UnsupportedScheme(s)) if s == "ftp"
+        ));
+    }
+}
@@ -0,0 +1,431 @@
+//! Backend delegating storage to an external helper process.
+//!
+//! `repository = "exec:/usr/local/bin/tape-helper"` launches the helper once
+//! per run (with `args`/`env` from `[settings.exec]`) and talks to it over
+//! stdin/stdout. Anything the helper writes to stderr is passed through.
+//!
+//! ## Protocol (version 1)
+//!
+//! Requests are single lines; keys are the rest of the line after the fixed
+//! fields and never contain newlines. Payloads follow their header line as
+//! exactly `<len>` raw bytes.
+//!
+//! ```text
+//! → HELLO 1                       ← OK
+//! → PUT <len> <key>\n<bytes>      ← OK
+//! → GET <key>                     ← DATA <len>\n<bytes>   | MISSING
+//! → LIST <prefix>                 ← ENTRY <size> <key>... then END
+//! → DELETE <key>                  ← OK                    (also when missing)
+//! → STAT <key>                    ← SIZE <n>              | MISSING
+//! → RANGE <offset> <len> <key>    ← DATA <len>\n<bytes>   | MISSING
+//! → QUIT                          (helper exits)
+//! ```
+//!
+//! Any request may be answered with `ERR <message>`. `STAT` and `RANGE` are
+//! optional: a helper may answer `UNSUPPORTED`, and but-next falls back to
+//! `LIST` and `GET`.
+
+use super::{Backend, BackendResult, ObjectInfo};
+use crate::config::ExecSettings;
+use crate::error::BackendError;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Protocol version sent in the `HELLO` handshake.
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How long the helper gets to exit after `QUIT` before it is killed.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A repository stored by an external helper program.
+pub struct ProcessBackend {
+    program: String,
+    child: Option<Child>,
+    conn: Mutex<Connection>,
+}
+
+/// The helper's stdio streams.
+struct Connection {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+}
+
+/// A parsed response line.
+#[derive(Debug, PartialEq, Eq)]
+enum Reply {
+    Ok,
+    Data(usize),
+    Missing,
+    Size(u64),
+    Entry(u64, String),
+    End,
+    Unsupported,
+    Err(String),
+}
+
+impl ProcessBackend {
+    /// Launches the helper named by the part of the repository URL after `exec:`.
+    pub fn open(program: &str, settings: &ExecSettings) -> BackendResult<Self> {
+        if program.is_empty() {
+            return Err(BackendError::InvalidUrl {
+                url: "exec:".to_string(),
+                message: "missing helper program".to_string(),
+            });
+        }
+
+        let mut child = Command::new(program)
+            .args(&settings.args)
+            .envs(&settings.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| BackendError::Io {
+                key: program.to_string(),
+                source: e,
+            })?;
+
+        let writer = child.stdin.take().expect("stdin is piped");
+        let reader = child.stdout.take().expect("stdout is piped");
+        let mut backend = Self::with_streams(program, BufReader::new(reader), writer)?;
+        backend.child = Some(child);
+        Ok(backend)
+    }
+
+    /// Speaks the protocol over arbitrary streams and performs the handshake.
+    fn with_streams(
+        program: &str,
+        reader: impl BufRead + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> BackendResult<Self> {
+        let backend = Self {
+            program: program.to_string(),
+            child: None,
+            conn: Mutex::new(Connection {
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+            }),
+        };
+
+        let mut conn = backend.lock();
+        match backend.request(&mut conn, &format!("HELLO {PROTOCOL_VERSION}"), None)? {
+            Reply::Ok => {}
+            other => return Err(backend.unexpected("handshake", other)),
+        }
+        drop(conn);
+        Ok(backend)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends one request line (plus payload) and reads the reply line.
+    fn request(
+        &self,
+        conn: &mut Connection,
+        line: &str,
+        payload: Option<&[u8]>,
+    ) -> BackendResult<Reply> {
+        let io = |e| self.io_error(e);
+        conn.writer.write_all(line.as_bytes()).map_err(io)?;
+        conn.writer.write_all(b"\n").map_err(io)?;
+        if let Some(payload) = payload {
+            conn.writer.write_all(payload).map_err(io)?;
+        }
+        conn.writer.flush().map_err(io)?;
+        self.read_reply(conn)
+    }
+
+    fn read_reply(&self, conn: &mut Connection) -> BackendResult<Reply> {
+        let mut line = String::new();
+        if conn
+            .reader
+            .read_line(&mut line)
+            .map_err(|e| self.io_error(e))?
+            == 0
+        {
+            return Err(self.remote("helper closed its output"));
+        }
+        let line = line.trim_end_matches(['\n', '\r']);
+        parse_reply(line).ok_or_else(|| self.remote(&format!("malformed reply '{line}'")))
+    }
+
+    fn read_payload(&self, conn: &mut Connection, len: usize) -> BackendResult<Vec<u8>> {
+        let mut data = vec![0; len];
+        conn.reader
+            .read_exact(&mut data)
+            .map_err(|e| self.io_error(e))?;
+        Ok(data)
+    }
+
+    /// Handles `DATA`/`MISSING` replies for `GET` and `RANGE`.
+    fn data(&self, conn: &mut Connection, key: &str, reply: Reply) -> BackendResult<Vec<u8>> {
+        match reply {
+            Reply::Data(len) => self.read_payload(conn, len),
+            Reply::Missing => Err(BackendError::NotFound(key.to_string())),
+            other => Err(self.unexpected(key, other)),
+        }
+    }
+
+    fn unexpected(&self, context: &str, reply: Reply) -> BackendError {
+        match reply {
+            Reply::Err(message) => self.remote(&format!("{context}: {message}")),
+            other => self.remote(&format!("{context}: unexpected reply {other:?}")),
+        }
+    }
+
+    fn remote(&self, message: &str) -> BackendError {
+        BackendError::Remote {
+            message: format!("{}: {message}", self.program),
+        }
+    }
+
+    fn io_error(&self, source: std::io::Error) -> BackendError {
+        BackendError::Io {
+            key: self.program.clone(),
+            source,
+        }
+    }
+}
+
+impl Backend for ProcessBackend {
+    fn location(&self) -> String {
+        format!("exec:{}", self.program)
+    }
+
+    fn list(&self, prefix: &str) -> BackendResult<Vec<ObjectInfo>> {
+        check_key(prefix)?;
+        let mut conn = self.lock();
+        let mut reply = self.request(&mut conn, &format!("LIST {prefix}"), None)?;
+        let mut objects = Vec::new();
+        loop {
+            match reply {
+                Reply::Entry(size, key) => objects.push(ObjectInfo { key, size }),
+                Reply::End => return Ok(objects),
+                other => return Err(self.unexpected(prefix, other)),
+            }
+            reply = self.read_reply(&mut conn)?;
+        }
+    }
+
+    fn read(&self, key: &str) -> BackendResult<Vec<u8>> {
+        check_key(key)?;
+        let mut conn = self.lock();
+        let reply = self.request(&mut conn, &format!("GET {key}"), None)?;
+        self.data(&mut conn, key, reply)
+    }
+
+    fn read_range(&self, key: &str, offset: u64, length: u64) -> BackendResult<Vec<u8>> {
+        check_key(key)?;
+        let mut conn = self.lock();
+        let reply = self.request(&mut conn, &format!("RANGE {offset} {length} {key}"), None)?;
+        if reply != Reply::Unsupported {
+            return self.data(&mut conn, key, reply);
+        }
+        drop(conn);
+
+        let data = self.read(key)?;
+        let start = (offset as usize).min(data.len());
+        let end = start.saturating_add(length as usize).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    fn write(&self, key: &str, data: &[u8]) -> BackendResult<()> {
+        check_key(key)?;
+        let mut conn = self.lock();
+        match self.request(&mut conn, &format!("PUT {} {key}", data.len()), Some(data))? {
+            Reply::Ok => Ok(()),
+            other => Err(self.unexpected(key, other)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> BackendResult<()> {
+        check_key(key)?;
+        let mut conn = self.lock();
+        match self.request(&mut conn, &format!("DELETE {key}"), None)? {
+            Reply::Ok | Reply::Missing => Ok(()),
+            other => Err(self.unexpected(key, other)),
+        }
+    }
+
+    fn exists(&self, key: &str) -> BackendResult<bool> {
+        Ok(self.size(key)?.is_some())
+    }
+
+    fn size(&self, key: &str) -> BackendResult<Option<u64>> {
+        check_key(key)?;
+        let mut conn = self.lock();
+        match self.request(&mut conn, &format!("STAT {key}"), None)? {
+            Reply::Size(size) => return Ok(Some(size)),
+            Reply::Missing => return Ok(None),
+            Reply::Unsupported => {}
+            other => return Err(self.unexpected(key, other)),
+        }
+        drop(conn);
+
+        Ok(self
+            .list(key)?
+            .into_iter()
+            .find(|object| object.key == key)
+            .map(|object| object.size))
+    }
+}
+
+impl Drop for ProcessBackend {
+    fn drop(&mut self) {
+        let conn = self.conn.get_mut().unwrap_or_else(|e| e.into_inner());
+        let _ = conn.writer.write_all(b"QUIT\n");
+        let _ = conn.writer.flush();
+        // Closing stdin lets helpers that ignore QUIT see EOF
+        conn.writer = Box::new(std::io::sink());
+        if let Some(child) = &mut self.child {
+            let deadline = Instant::now() + QUIT_TIMEOUT;
+            while matches!(child.try_wait(), Ok(None)) {
+                if Instant::now() >= deadline {
+                    eprintln!(
+                        "warning: helper {} did not exit after QUIT; killing it",
+                        self.program
+                    );
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+}
+
+/// Keys are sent as the tail of a request line, so they must be single-line.
+fn check_key(key: &str) -> BackendResult<()> {
+    if key.contains(['\n', '\r']) {
+        return Err(BackendError::Remote {
+            message: format!("key {key:?} cannot be sent to a helper"),
+        });
+    }
+    Ok(())
+}
+
+fn parse_reply(line: &str) -> Option<Reply> {
+    let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+    Some(match word {
+        "OK" => Reply::Ok,
+        "DATA" => Reply::Data(rest.parse().ok()?),
+        "MISSING" => Reply::Missing,
+        "SIZE" => Reply::Size(rest.parse().ok()?),
+        "ENTRY" => {
+            let (size, key) = rest.split_once(' ')?;
+            Reply::Entry(size.parse().ok()?, key.to_string())
+        }
+        "END" => Reply::End,
+        "UNSUPPORTED" => Reply::Unsupported,
+        "ERR" => Reply::Err(rest.to_string()),
+        _ => return None,
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::os::unix::net::UnixStream;
+
+    /// Minimal helper implementing only the required verbs, as a third-party
+    /// helper would.
+    fn reference_helper(stream: UnixStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut out = stream;
+        let mut store: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            let request = line.trim_end().to_string();
+            line.clear();
+            let (verb, rest) = request.split_once(' ').unwrap_or((&request, ""));
+            match verb {
+                "HELLO" => writeln!(out, "OK").unwrap(),
+                "PUT" => {
+                    let (len, key) = rest.split_once(' ').unwrap();
+                    let mut data = vec![0; len.parse().unwrap()];
+                    reader.read_exact(&mut data).unwrap();
+                    store.insert(key.to_string(), data);
+                    writeln!(out, "OK").unwrap();
+                }
+                "GET" => match store.get(rest) {
+                    Some(data) => {
+                        writeln!(out, "DATA {}", data.len()).unwrap();
+                        out.write_all(data).unwrap();
+                    }
+                    None => writeln!(out, "MISSING").unwrap(),
+                },
+                "LIST" => {
+                    for (key, data) in store.range(rest.to_string()..) {
+                        if !key.starts_with(rest) {
+                            break;
+                        }
+                        writeln!(out, "ENTRY {} {key}", data.len()).unwrap();
+                    }
+                    writeln!(out, "END").unwrap();
+                }
+                "DELETE" => {
+                    store.remove(rest);
+                    writeln!(out, "OK").unwrap();
+                }
+                "QUIT" => return,
+                _ => writeln!(out, "UNSUPPORTED").unwrap(),
+            }
+        }
+    }
+
+    fn connect() -> ProcessBackend {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        std::thread::spawn(move || reference_helper(theirs));
+        let reader = BufReader::new(ours.try_clone().unwrap());
+        ProcessBackend::with_streams("test-helper", reader, ours).unwrap()
+    }
+
+    #[test]
+    fn roundtrip_with_required_verbs_only() {
+        let backend = connect();
+        backend.write("snapshots/a b.json", b"{}").unwrap();
+        backend.write("blobs/ab/cdef", b"0123456789").unwrap();
+
+        assert_eq!(backend.read("snapshots/a b.json").unwrap(), b"{}");
+        assert_eq!(backend.read_range("blobs/ab/cdef", 8, 5).unwrap(), b"89");
+        assert_eq!(backend.size("blobs/ab/cdef").unwrap(), Some(10));
+        assert!(!backend.exists("blobs/ab/cd").unwrap());
+        assert!(matches!(
+            backend.read("blobs/ab/0000"),
+            Err(BackendError::NotFound(_))
+        ));
+
+        let keys: Vec<_> = backend
+            .list("")
+            .unwrap()
+            .into_iter()
+            .map(|o| o.key)
+            .collect();
+        assert_eq!(keys, vec!["blobs/ab/cdef", "snapshots/a b.json"]);
+
+        backend.delete("blobs/ab/cdef").unwrap();
+        assert!(backend.list("blobs/").unwrap().is_empty());
+        assert!(backend.write("bad\nkey", b"").is_err());
+    }
+
+    #[test]
+    fn reply_parsing() {
+        assert_eq!(parse_reply("DATA 12"), Some(Reply::Data(12)));
+        assert_eq!(
+            parse_reply("ENTRY 7 snapshots/a b.json"),
+            Some(Reply::Entry(7, "snapshots/a b.json".to_string()))
+        );
+        assert_eq!(
+            parse_reply("ERR tape offline"),
+            Some(Reply::Err("tape offline".to_string()))
+        );
+        assert_eq!(parse_reply("SIZE x"), None);
+        assert_eq!(parse_reply("HELLO"), None);
+    }
+}
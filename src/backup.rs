@@ -14,50 +14,141 @@
 //! targets or at different points in time) have identical content, the blob is
 //! stored only once.
 
+use crate::backend::{self, Backend};
 use crate::compress;
 use crate::config::{BackupTarget, Config, Settings};
+use crate::copy;
 use crate::crypto;
-use crate::error::Result;
+use crate::error::{BackupError, ButError, Result};
+use crate::filter::TargetWalk;
 use crate::hasher;
 use crate::manifest::{self, FileEntry, Snapshot, SnapshotStats};
+use crate::parity;
+use crate::retention::{self, Reason, RetentionPolicy};
 use indicatif::{ProgressBar, ProgressStyle};
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::time::Instant;
-use walkdir::WalkDir;
+
+/// Options for [`backup_target`] and [`backup_all`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackupOptions<'a> {
+    /// Encryption password; blobs are only encrypted if `settings.encrypt` is set.
+    pub password: Option<&'a str>,
+
+    /// Tags recorded on every snapshot created.
+    pub tags: &'a [String],
+
+    /// Description recorded on every snapshot created.
+    pub message: Option<&'a str>,
+
+    /// Fail with [`BackupError::NothingChanged`] instead of recording a
+    /// snapshot identical to the target's previous one.
+    pub skip_unchanged: bool,
+
+    /// Stops the backup with [`BackupError::Interrupted`] once set, after
+    /// the file being stored; no targets are started after that.
+    pub cancel: Option<&'a AtomicBool>,
+
+    /// Updated as files are stored, for status reporting.
+    pub progress: Option<&'a Progress>,
+
+    pub verbose: bool,
+}
+
+impl BackupOptions<'_> {
+    fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+}
+
+/// Live progress of a running backup.
+#[derive(Debug, Default)]
+pub struct Progress(Mutex<ProgressState>);
+
+/// A point-in-time view of [`Progress`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgressState {
+    /// Target being backed up, if any.
+    pub target: Option<String>,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+}
+
+impl Progress {
+    pub fn get(&self) -> ProgressState {
+        self.lock().clone()
+    }
+
+    /// Resets the counters for `target`; they are cleared again when the
+    /// returned guard is dropped, however the backup ends.
+    fn start(&self, target: &str, files_total: u64) -> ProgressGuard<'_> {
+        *self.lock() = ProgressState {
+            target: Some(target.to_string()),
+            files_total,
+            ..ProgressState::default()
+        };
+        ProgressGuard(self)
+    }
+
+    fn advance(&self, bytes: u64) {
+        let mut state = self.lock();
+        state.files_done += 1;
+        state.bytes_done += bytes;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ProgressState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+struct ProgressGuard<'a>(&'a Progress);
+
+impl Drop for ProgressGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.lock() = ProgressState::default();
+    }
+}
 
 /// Executes a backup for a single target, returning the created snapshot.
+/// `parent` is the target's latest snapshot, if it has one.
 pub fn backup_target(
     settings: &Settings,
+    repo: &dyn Backend,
     name: &str,
     target: &BackupTarget,
-    password: Option<&str>,
-    verbose: bool,
+    parent: Option<&Snapshot>,
+    opts: &BackupOptions,
 ) -> Result<Snapshot> {
-    let source = &target.from;
-    let repo_path = &settings.repo_path;
-
-    // Ensure source exists
-    if !source.exists() {
-        return Err(crate::error::BackupError::SourceNotFound(source.clone()).into());
+    let BackupOptions {
+        password, verbose, ..
+    } = *opts;
+    // Ensure every source exists
+    if let Some(missing) = target.from.iter().find(|source| !source.exists()) {
+        return Err(BackupError::SourceNotFound(missing.clone()).into());
     }
 
-    // Initialize repo if needed
-    manifest::init_repo(repo_path)?;
-
     let compression = target.compression.unwrap_or(settings.compression);
     let encrypted = settings.encrypt && password.is_some();
 
-    let mut snapshot = Snapshot::new(name, source.clone(), compression, encrypted);
+    let mut snapshot = Snapshot::new(name, target.source_path(), compression, encrypted);
+    if target.from.len() > 1 {
+        snapshot.sources = target.from.clone();
+    }
+    snapshot.add_tags(opts.tags);
+    snapshot.message = opts.message.map(String::from);
 
-    // Collect all files first for progress tracking
-    let files: Vec<_> = WalkDir::new(source)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| !is_excluded(e.path(), source, &target.exclude))
-        .collect();
+    // Collect all files first for progress tracking, with their paths in
+    // the snapshot
+    let mut walk = TargetWalk::new(target)?;
+    let files: Vec<_> = walk.by_ref().collect();
+    let skipped = walk.skipped();
 
     let total_files = files.len() as u64;
+    let _progress = opts.progress.map(|p| p.start(name, total_files));
     let pb = create_progress_bar(total_files, name);
 
     let start = Instant::now();
@@ -65,17 +156,15 @@ pub fn backup_target(
     let mut total_stored_size = 0u64;
     let mut dedup_count = 0u64;
 
-    for entry in &files {
-        let path = entry.path();
-        let relative = path
-            .strip_prefix(source)
-            .unwrap_or(path)
-            .to_string_lossy()
-            .to_string();
-
-        // Normalize path separators for cross-platform consistency
-        let relative = relative.replace('\\', "/");
+    for (entry, relative) in files {
+        // Blobs stored so far are harmless without a manifest and are reused
+        // by the next backup
+        if opts.cancelled() {
+            pb.abandon();
+            return Err(BackupError::Interrupted.into());
+        }
 
+        let path = entry.path();
         pb.set_message(truncate_path(&relative, 40));
 
         // Get file metadata
@@ -102,7 +191,7 @@ pub fn backup_target(
         let hash = hasher::hash_file(path)?;
 
         // Check for deduplication
-        if manifest::blob_exists(repo_path, &hash) {
+        if manifest::blob_exists(repo, &hash)? {
             dedup_count += 1;
             snapshot.add_file(
                 relative,
@@ -113,9 +202,13 @@ pub fn backup_target(
                     permissions,
                     modified,
                     deduplicated: true,
+                    damaged: false,
                 },
             );
             pb.inc(1);
+            if let Some(progress) = opts.progress {
+                progress.advance(file_size);
+            }
             continue;
         }
 
@@ -133,7 +226,10 @@ pub fn backup_target(
         total_stored_size += stored_size;
 
         // Store the blob
-        manifest::store_blob(repo_path, &hash, &final_data)?;
+        manifest::store_blob(repo, &hash, &final_data)?;
+        if settings.parity > 0 {
+            parity::store_parity(repo, &hash, &final_data, settings.parity)?;
+        }
 
         if verbose {
             let ratio = compress::ratio(file_size, stored_size);
@@ -156,10 +252,14 @@ pub fn backup_target(
                 permissions,
                 modified,
                 deduplicated: false,
+                damaged: false,
             },
         );
 
         pb.inc(1);
+        if let Some(progress) = opts.progress {
+            progress.advance(file_size);
+        }
     }
 
     let duration = start.elapsed();
@@ -174,99 +274,394 @@ pub fn backup_target(
         stored_size: total_stored_size,
         deduplicated_blobs: dedup_count,
         duration_ms: duration.as_millis() as u64,
+        skipped_files: skipped.files,
+        skipped_mounts: skipped.mounts,
     };
 
+    if let Some(parent) = parent {
+        snapshot.parent = Some(parent.id.clone());
+        if snapshot.same_files(parent) {
+            if opts.skip_unchanged {
+                return Err(BackupError::NothingChanged(parent.id.clone()).into());
+            }
+            snapshot.unchanged = true;
+            // Refer to the snapshot holding the files, not to another
+            // reference, so that only it has to be kept
+            if parent.unchanged {
+                snapshot.parent = parent.parent.clone();
+            }
+        }
+    }
+
     // Save the snapshot manifest
-    manifest::save_snapshot(repo_path, &snapshot)?;
+    manifest::save_snapshot(repo, &snapshot)?;
 
     Ok(snapshot)
 }
 
+/// Outcome of [`backup_targets`].
+#[derive(Debug, Default)]
+pub struct BackupRun {
+    pub snapshots: Vec<Snapshot>,
+    /// Targets skipped because nothing changed.
+    pub unchanged: Vec<String>,
+    /// Targets whose backup failed, with the reason.
+    pub failed: Vec<(String, ButError)>,
+}
+
 /// Runs backup for all targets defined in the configuration.
-pub fn backup_all(config: &Config, password: Option<&str>, verbose: bool) -> Result<Vec<Snapshot>> {
-    let mut snapshots = Vec::new();
+pub fn backup_all(config: &Config, repo: &dyn Backend, opts: &BackupOptions) -> Result<BackupRun> {
+    let names: Vec<&str> = config.backup.keys().map(String::as_str).collect();
+    backup_targets(config, repo, &names, opts)
+}
+
+/// Runs backup for the named targets, then prunes and mirrors as configured.
+/// A dynamic target's name selects all of its sub-targets. A failing target
+/// is reported and doesn't stop the others.
+pub fn backup_targets(
+    config: &Config,
+    repo: &dyn Backend,
+    names: &[&str],
+    opts: &BackupOptions,
+) -> Result<BackupRun> {
+    let mut run = BackupRun::default();
+
+    // Dynamic targets expand into the sub-targets matching right now
+    let mut targets = Vec::new();
+    for name in names {
+        let selected = config.select_targets(name);
+        if let Some(target) = config.configured(name).filter(|t| t.is_dynamic()) {
+            if selected.is_empty() {
+                eprintln!(
+                    "\n  {} {name}: no directory matches {}",
+                    colored::Colorize::yellow("!"),
+                    target.display_sources(),
+                );
+            }
+        }
+        targets.extend(selected);
+    }
+    let parents = manifest::latest_snapshots(repo)?;
 
-    for (name, target) in &config.backup {
+    for (name, target) in &targets {
+        if opts.cancelled() {
+            break;
+        }
         eprintln!(
             "\n{} Backing up: {}",
             colored::Colorize::bold(colored::Colorize::cyan("▶")),
             colored::Colorize::bold(name.as_str()),
         );
-        eprintln!("  Source: {}", target.from.display());
+        eprintln!("  Source: {}", target.display_sources());
 
-        match backup_target(&config.settings, name, target, password, verbose) {
+        let parent = parents.get(name);
+        match backup_target(&config.settings, repo, name, target, parent, opts) {
             Ok(snapshot) => {
                 print_snapshot_summary(&snapshot);
-                snapshots.push(snapshot);
+                if config.settings.auto_prune {
+                    auto_prune(config, repo, name);
+                }
+                run.snapshots.push(snapshot);
+            }
+            Err(ButError::Backup(BackupError::NothingChanged(parent))) => {
+                print_nothing_changed(&parent);
+                run.unchanged.push(name.clone());
+            }
+            Err(e @ ButError::Backup(BackupError::Interrupted)) => {
+                eprintln!(
+                    "  {} Interrupted; no snapshot was written",
+                    colored::Colorize::yellow("!"),
+                );
+                run.failed.push((name.clone(), e));
             }
             Err(e) => {
                 eprintln!("  {} Failed: {e}", colored::Colorize::red("✗"),);
+                run.failed.push((name.clone(), e));
             }
         }
     }
 
-    Ok(snapshots)
+    if let Some(mirror) = &config.settings.mirror {
+        if !run.snapshots.is_empty() && opts.cancelled() {
+            eprintln!(
+                "  {} Stopping; not mirrored: {}",
+                colored::Colorize::yellow("!"),
+                run.snapshots
+                    .iter()
+                    .map(|s| s.id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        } else if !run.snapshots.is_empty() {
+            mirror_snapshots(
+                config,
+                mirror,
+                repo,
+                &run.snapshots,
+                opts.password,
+                opts.verbose,
+            );
+        }
+    }
+
+    Ok(run)
 }
 
-/// Prunes old snapshots, keeping only the most recent `keep` per target.
-pub fn prune_snapshots(repo_path: &Path, target: &str, keep: usize) -> Result<(usize, u64)> {
-    let mut snapshots = manifest::list_snapshots_for_target(repo_path, target)?;
+/// Copies freshly created snapshots to the configured mirror. Failures are
+/// reported but don't fail the backup: the primary copy is already safe.
+fn mirror_snapshots(
+    config: &Config,
+    mirror: &str,
+    repo: &dyn Backend,
+    snapshots: &[Snapshot],
+    password: Option<&str>,
+    verbose: bool,
+) {
+    eprintln!(
+        "\n{} Mirroring to: {}",
+        colored::Colorize::bold(colored::Colorize::cyan("▶")),
+        colored::Colorize::bold(mirror),
+    );
+
+    let opts = copy::CopyOptions {
+        password,
+        dest_password: None,
+        parity: config.settings.parity,
+        verbose,
+    };
+    let result = backend::open_url(mirror, &config.settings)
+        .map_err(Into::into)
+        .and_then(|dest| copy::copy_snapshots(repo, dest.as_ref(), snapshots, &opts));
 
-    if snapshots.len() <= keep {
-        return Ok((0, 0));
+    match result {
+        Ok(report) => eprintln!(
+            "  {} Mirrored {} snapshot(s), {} new blob(s) ({})",
+            colored::Colorize::green("✓"),
+            report.snapshots_copied,
+            report.blobs_copied,
+            format_size(report.bytes_copied),
+        ),
+        Err(e) => eprintln!("  {} Mirror failed: {e}", colored::Colorize::red("✗")),
     }
+}
 
-    // Sort newest first
-    snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+/// A snapshot's fate in a [`PrunePlan`].
+#[derive(Debug, Clone)]
+pub struct PlannedSnapshot {
+    pub snapshot: Snapshot,
+    pub keep: bool,
+    pub reasons: Vec<Reason>,
+    /// Bytes freed by removing this snapshot alone: blobs (and parity) no
+    /// other snapshot references. Zero for kept snapshots.
+    pub frees: u64,
+}
 
-    let to_delete = &snapshots[keep..];
-    let mut deleted = 0usize;
-    let mut freed = 0u64;
+/// What a prune would do, computed without modifying the repository.
+#[derive(Debug, Default)]
+pub struct PrunePlan {
+    /// Per-target decisions, newest snapshot first.
+    pub targets: Vec<(String, Vec<PlannedSnapshot>)>,
 
-    for snap in to_delete {
-        let bytes = manifest::delete_snapshot(repo_path, snap)?;
-        freed += bytes;
-        deleted += 1;
+    /// Bytes freed by removing all planned snapshots. Larger than the sum of
+    /// the per-snapshot figures when removed snapshots share blobs.
+    pub total_frees: u64,
+}
+
+impl PrunePlan {
+    /// Snapshots the plan removes.
+    pub fn removals(&self) -> impl Iterator<Item = &PlannedSnapshot> {
+        self.targets
+            .iter()
+            .flat_map(|(_, planned)| planned)
+            .filter(|p| !p.keep)
     }
+}
 
-    Ok((deleted, freed))
+/// Evaluates retention policies for the given targets (capped at
+/// `max_snapshots`, 0 = no cap) and works out how much space each removal
+/// reclaims, accounting for blobs shared with every other snapshot in the
+/// repository.
+pub fn plan_prune(
+    repo: &dyn Backend,
+    policies: &[(String, RetentionPolicy)],
+    max_snapshots: usize,
+) -> Result<PrunePlan> {
+    let all = manifest::list_snapshots(repo)?;
+    let mut plan = PrunePlan::default();
+
+    for (target, policy) in policies {
+        let snapshots: Vec<Snapshot> = all
+            .iter()
+            .filter(|s| &s.target_name == target)
+            .cloned()
+            .collect();
+        let planned = retention::evaluate(&snapshots, policy, max_snapshots)?
+            .into_iter()
+            .map(|d| PlannedSnapshot {
+                snapshot: d.snapshot.clone(),
+                keep: d.keep,
+                reasons: d.reasons,
+                frees: 0,
+            })
+            .collect();
+        plan.targets.push((target.clone(), planned));
+    }
+
+    keep_referenced(&all, &mut plan);
+    account_frees(repo, &all, &mut plan)?;
+    Ok(plan)
 }
 
-// ─── Helpers ────────────────────────────────────────────────────────────────
+/// Plans the removal of exactly `selected` (pinned snapshots are kept), with
+/// the same space accounting as [`plan_prune`].
+pub fn plan_removal(repo: &dyn Backend, selected: &[Snapshot]) -> Result<PrunePlan> {
+    let all = manifest::list_snapshots(repo)?;
+    let mut plan = PrunePlan::default();
 
-/// Checks if a path matches any exclusion glob pattern.
-fn is_excluded(path: &Path, base: &Path, patterns: &[String]) -> bool {
-    let relative = path.strip_prefix(base).unwrap_or(path);
-    let rel_str = relative.to_string_lossy();
+    let mut seen = HashSet::new();
+    for snapshot in selected.iter().filter(|s| seen.insert(s.id.as_str())) {
+        let planned = PlannedSnapshot {
+            snapshot: snapshot.clone(),
+            keep: snapshot.is_pinned(),
+            reasons: vec![if snapshot.is_pinned() {
+                Reason::Pinned(snapshot.pinned_until)
+            } else {
+                Reason::Selected
+            }],
+            frees: 0,
+        };
+        match plan
+            .targets
+            .iter_mut()
+            .find(|(t, _)| *t == snapshot.target_name)
+        {
+            Some((_, planned_for_target)) => planned_for_target.push(planned),
+            None => plan
+                .targets
+                .push((snapshot.target_name.clone(), vec![planned])),
+        }
+    }
+    for (_, planned) in &mut plan.targets {
+        planned.sort_by_key(|p| std::cmp::Reverse(p.snapshot.created_at));
+    }
 
-    for pattern in patterns {
-        // Simple glob matching: supports trailing * and /
-        let pat = pattern.trim_end_matches('/');
+    keep_referenced(&all, &mut plan);
+    account_frees(repo, &all, &mut plan)?;
+    Ok(plan)
+}
 
-        if let Some(suffix) = pat.strip_prefix('*') {
-            // Suffix match: *.tmp matches any .tmp file
-            if rel_str.ends_with(suffix) {
-                return true;
-            }
-        } else if let Some(prefix) = pat.strip_suffix('*') {
-            // Prefix match
-            if rel_str.starts_with(prefix) {
-                return true;
-            }
-        } else {
-            // Exact or component match
-            let components: Vec<_> = relative.components().collect();
-            for component in &components {
-                if component.as_os_str().to_string_lossy() == pat.trim_end_matches('/') {
-                    return true;
-                }
-            }
+/// Keeps the snapshots that unchanged snapshots staying in the repository
+/// take their files from.
+fn keep_referenced(all: &[Snapshot], plan: &mut PrunePlan) {
+    let removed: HashSet<String> = plan.removals().map(|p| p.snapshot.id.clone()).collect();
+    let referenced: HashSet<&str> = all
+        .iter()
+        .filter(|s| s.unchanged && !removed.contains(&s.id))
+        .filter_map(|s| s.parent.as_deref())
+        .collect();
+    for planned in plan.targets.iter_mut().flat_map(|(_, planned)| planned) {
+        if !planned.keep && referenced.contains(planned.snapshot.id.as_str()) {
+            planned.keep = true;
+            planned.reasons = vec![Reason::Referenced];
+        }
+    }
+}
+
+/// Fills in the space each planned removal reclaims. `all` is every snapshot
+/// in the repository.
+fn account_frees(repo: &dyn Backend, all: &[Snapshot], plan: &mut PrunePlan) -> Result<()> {
+    let removed: HashSet<String> = plan.removals().map(|p| p.snapshot.id.clone()).collect();
+    if removed.is_empty() {
+        return Ok(());
+    }
+
+    // Blobs still referenced after the prune, and how many removed
+    // snapshots reference each of the others
+    let retained: HashSet<&str> = all
+        .iter()
+        .filter(|s| !removed.contains(s.id.as_str()))
+        .flat_map(|s| s.files.values().map(|e| e.hash.as_str()))
+        .collect();
+    let mut orphaned: HashMap<String, usize> = HashMap::new();
+    for planned in plan.removals() {
+        let hashes: HashSet<&str> = planned
+            .snapshot
+            .files
+            .values()
+            .map(|e| e.hash.as_str())
+            .collect();
+        for hash in hashes.into_iter().filter(|h| !retained.contains(h)) {
+            *orphaned.entry(hash.to_string()).or_default() += 1;
+        }
+    }
+
+    let sizes: HashMap<String, u64> = repo
+        .list("blobs/")?
+        .into_iter()
+        .chain(repo.list("parity/")?)
+        .map(|o| (o.key, o.size))
+        .collect();
+    let stored = |hash: &str| {
+        sizes.get(&manifest::blob_key(hash)).copied().unwrap_or(0)
+            + sizes.get(&manifest::parity_key(hash)).copied().unwrap_or(0)
+    };
+
+    plan.total_frees = orphaned.keys().map(|h| stored(h)).sum();
+    for (_, planned) in &mut plan.targets {
+        for p in planned.iter_mut().filter(|p| !p.keep) {
+            let hashes: HashSet<&str> =
+                p.snapshot.files.values().map(|e| e.hash.as_str()).collect();
+            p.frees = hashes
+                .into_iter()
+                .filter(|h| orphaned.get(*h) == Some(&1))
+                .map(stored)
+                .sum();
         }
     }
 
-    false
+    Ok(())
+}
+
+/// Deletes the snapshots a plan removes. Returns the number of snapshots
+/// deleted and the bytes freed.
+pub fn execute_prune(repo: &dyn Backend, plan: &PrunePlan) -> Result<(usize, u64)> {
+    let mut deleted = 0usize;
+    let mut freed = 0u64;
+    for planned in plan.removals() {
+        freed += manifest::delete_snapshot(repo, &planned.snapshot)?;
+        deleted += 1;
+    }
+    Ok((deleted, freed))
+}
+
+/// Prunes a target's snapshots according to a retention policy (capped at
+/// `max_snapshots`, 0 = no cap). Returns the number of snapshots deleted and
+/// the bytes freed.
+pub fn prune_snapshots(
+    repo: &dyn Backend,
+    target: &str,
+    policy: &RetentionPolicy,
+    max_snapshots: usize,
+) -> Result<(usize, u64)> {
+    let plan = plan_prune(repo, &[(target.to_string(), policy.clone())], max_snapshots)?;
+    execute_prune(repo, &plan)
+}
+
+/// Applies a target's configured retention rules after a backup.
+pub fn auto_prune(config: &Config, repo: &dyn Backend, name: &str) {
+    let policy = config.retention_for(name);
+    match prune_snapshots(repo, name, &policy, config.settings.max_snapshots) {
+        Ok((0, _)) => {}
+        Ok((deleted, freed)) => eprintln!(
+            "    Pruned:      {deleted} snapshot(s), freed {}",
+            format_size(freed),
+        ),
+        Err(e) => eprintln!("  {} Prune failed: {e}", colored::Colorize::red("✗")),
+    }
 }
 
+// ─── Helpers ────────────────────────────────────────────────────────────────
+
 fn create_progress_bar(total: u64, target_name: &str) -> ProgressBar {
     let pb = ProgressBar::new(total);
     pb.set_style(
@@ -303,10 +698,25 @@ pub fn print_snapshot_summary(snapshot: &Snapshot) {
         colored::Colorize::green("✓"),
         colored::Colorize::bold(snapshot.id.as_str()),
     );
+    if !snapshot.tags.is_empty() {
+        eprintln!("    Tags:        {}", snapshot.tags.join(", "));
+    }
+    if snapshot.unchanged {
+        eprintln!(
+            "    Unchanged:   same files as {}",
+            snapshot.parent.as_deref().unwrap_or_default(),
+        );
+    }
     eprintln!(
         "    Files:       {} total, {} new, {} deduplicated",
         stats.total_files, stats.new_files, stats.deduplicated_blobs,
     );
+    if stats.skipped_files > 0 || stats.skipped_mounts > 0 {
+        eprintln!(
+            "    Skipped:     {} file(s) by size or age, {} mount point(s)",
+            stats.skipped_files, stats.skipped_mounts,
+        );
+    }
     eprintln!(
         "    Size:        {} → {} ({:.1}% ratio)",
         format_size(stats.total_size),
@@ -325,6 +735,15 @@ pub fn print_snapshot_summary(snapshot: &Snapshot) {
     eprintln!("    Duration:    {:.2}s", stats.duration_ms as f64 / 1000.0);
 }
 
+/// Reports a backup skipped because nothing changed since `parent`.
+pub fn print_nothing_changed(parent: &str) {
+    eprintln!(
+        "  {} No changes since {}, skipped",
+        colored::Colorize::dimmed("–"),
+        parent.get(..manifest::SHORT_ID_LEN).unwrap_or(parent),
+    );
+}
+
 /// Formats a byte count as a human-readable size string.
 pub fn format_size(bytes: u64) -> String {
     const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
@@ -337,3 +756,288 @@ pub fn format_size(bytes: u64) -> String {
     }
     format!("{size:.1} PiB")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::LocalBackend;
+    use crate::config::CompressionKind;
+    use chrono::{Duration, Utc};
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    fn add_snapshot(repo: &dyn Backend, id: &str, age_hours: i64, files: &[&[u8]]) {
+        let mut snap = Snapshot::new("docs", PathBuf::from("/"), CompressionKind::None, false);
+        snap.id = id.to_string();
+        snap.created_at = Utc::now() - Duration::hours(age_hours);
+        for (i, content) in files.iter().enumerate() {
+            let hash = hasher::hash_bytes(content);
+            manifest::store_blob(repo, &hash, content).unwrap();
+            snap.add_file(
+                format!("f{i}"),
+                FileEntry {
+                    hash,
+                    size: content.len() as u64,
+                    stored_size: content.len() as u64,
+                    permissions: None,
+                    modified: 0,
+                    deduplicated: false,
+                    damaged: false,
+                },
+            );
+        }
+        manifest::save_snapshot(repo, &snap).unwrap();
+    }
+
+    #[test]
+    fn plan_accounts_for_shared_blobs() {
+        let dir = std::env::temp_dir().join("but-next-test-backup-plan");
+        let _ = std::fs::remove_dir_all(&dir);
+        let repo = LocalBackend::new(dir.clone());
+
+        add_snapshot(&repo, "new", 0, &[b"kept", b"shared-with-new"]);
+        add_snapshot(
+            &repo,
+            "mid",
+            1,
+            &[b"shared-with-new", b"mid-only", b"old+mid"],
+        );
+        add_snapshot(&repo, "old", 2, &[b"old-only!!", b"old+mid"]);
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let plan = plan_prune(&repo, &[("docs".to_string(), policy)], 0).unwrap();
+        let frees: Vec<(&str, u64)> = plan
+            .removals()
+            .map(|p| (p.snapshot.id.as_str(), p.frees))
+            .collect();
+        assert_eq!(frees, vec![("mid", 8), ("old", 10)]);
+        // "old+mid" is only freed once both snapshots go
+        assert_eq!(plan.total_frees, 8 + 10 + 7);
+
+        let (deleted, freed) = execute_prune(&repo, &plan).unwrap();
+        assert_eq!((deleted, freed), (2, plan.total_frees));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unchanged_backups_are_skipped_or_flagged() {
+        let dir = std::env::temp_dir().join("but-next-test-backup-unchanged");
+        let _ = std::fs::remove_dir_all(&dir);
+        let source = dir.join("src");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"alpha").unwrap();
+        let repo = LocalBackend::new(dir.join("repo"));
+        let target = BackupTarget {
+            from: vec![source.clone()],
+            ..BackupTarget::default()
+        };
+        let settings = Settings::default();
+        let skip = BackupOptions {
+            skip_unchanged: true,
+            ..BackupOptions::default()
+        };
+
+        let first = backup_target(&settings, &repo, "docs", &target, None, &skip).unwrap();
+        assert_eq!(first.parent, None);
+        match backup_target(&settings, &repo, "docs", &target, Some(&first), &skip) {
+            Err(ButError::Backup(BackupError::NothingChanged(parent))) => {
+                assert_eq!(parent, first.id)
+            }
+            other => panic!("expected NothingChanged, got {other:?}"),
+        }
+
+        let opts = BackupOptions::default();
+        let recorded =
+            backup_target(&settings, &repo, "docs", &target, Some(&first), &opts).unwrap();
+        assert!(recorded.unchanged);
+        assert_eq!(recorded.parent.as_deref(), Some(first.id.as_str()));
+
+        std::fs::write(source.join("b.txt"), b"beta").unwrap();
+        let changed =
+            backup_target(&settings, &repo, "docs", &target, Some(&recorded), &skip).unwrap();
+        assert!(!changed.unchanged);
+        assert_eq!(changed.parent.as_deref(), Some(recorded.id.as_str()));
+
+        // Same files stored differently still make a new snapshot
+        let gzip = BackupTarget {
+            compression: Some(CompressionKind::Gzip),
+            ..target.clone()
+        };
+        let recompressed =
+            backup_target(&settings, &repo, "docs", &gzip, Some(&changed), &skip).unwrap();
+        assert!(!recompressed.unchanged);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unchanged_snapshots_share_their_parents_files() {
+        let dir = std::env::temp_dir().join("but-next-test-backup-shared");
+        let _ = std::fs::remove_dir_all(&dir);
+        let source = dir.join("src");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"alpha").unwrap();
+        let repo = LocalBackend::new(dir.join("repo"));
+        let target = BackupTarget {
+            from: vec![source.clone()],
+            ..BackupTarget::default()
+        };
+        let settings = Settings::default();
+        let opts = BackupOptions::default();
+
+        let base = backup_target(&settings, &repo, "docs", &target, None, &opts).unwrap();
+        let second = backup_target(&settings, &repo, "docs", &target, Some(&base), &opts).unwrap();
+        let third = backup_target(&settings, &repo, "docs", &target, Some(&second), &opts).unwrap();
+        assert_eq!(third.parent.as_deref(), Some(base.id.as_str()));
+
+        // Stored as a reference, read back with the files
+        let json = repo.read(&manifest::snapshot_key(&third.id)).unwrap();
+        let stored = Snapshot::from_json(&String::from_utf8_lossy(&json)).unwrap();
+        assert!(stored.files.is_empty());
+        let all = manifest::list_snapshots(&repo).unwrap();
+        assert!(all.iter().all(|s| s.same_files(&base)));
+
+        let error = manifest::delete_snapshot(&repo, &base).unwrap_err();
+        assert!(error.to_string().contains("unchanged"));
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..RetentionPolicy::default()
+        };
+        let plan = plan_prune(&repo, &[("docs".to_string(), policy)], 0).unwrap();
+        let removed: Vec<&str> = plan.removals().map(|p| p.snapshot.id.as_str()).collect();
+        assert_eq!(removed, [second.id.as_str()]);
+        execute_prune(&repo, &plan).unwrap();
+        let all = manifest::list_snapshots(&repo).unwrap();
+        assert_eq!(all.len(), 2);
+        assert!(all[1].same_files(&base));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multiple_sources_restore_under_their_paths() {
+        let dir = std::env::temp_dir().join("but-next-test-backup-sources");
+        let _ = std::fs::remove_dir_all(&dir);
+        let etc = dir.join("etc");
+        let app = dir.join("var/lib/app");
+        std::fs::create_dir_all(&etc).unwrap();
+        std::fs::create_dir_all(&app).unwrap();
+        std::fs::write(etc.join("hosts"), b"127.0.0.1").unwrap();
+        std::fs::write(app.join("db"), b"rows").unwrap();
+        let repo = LocalBackend::new(dir.join("repo"));
+        let target = BackupTarget {
+            from: vec![etc.clone(), app.clone()],
+            ..BackupTarget::default()
+        };
+
+        let snapshot = backup_target(
+            &Settings::default(),
+            &repo,
+            "system",
+            &target,
+            None,
+            &BackupOptions::default(),
+        )
+        .unwrap();
+        let relative = |path: &Path| path.strip_prefix(&snapshot.source_path).unwrap().to_owned();
+        assert_eq!(snapshot.sources, target.from);
+        assert_eq!(snapshot.files.len(), 2);
+
+        let restore = |target_dir: Option<PathBuf>| {
+            let opts = crate::restore::RestoreOptions {
+                target_dir,
+                password: None,
+                force: true,
+                verify: true,
+                filter: None,
+                verbose: false,
+            };
+            crate::restore::restore_snapshot(&repo, &snapshot, &opts).unwrap()
+        };
+
+        // Each source in its own subdirectory
+        let out = dir.join("out");
+        assert_eq!(restore(Some(out.clone())).files_restored, 2);
+        let hosts = out.join(relative(&etc)).join("hosts");
+        assert_eq!(std::fs::read(hosts).unwrap(), b"127.0.0.1");
+        assert_eq!(
+            std::fs::read(out.join(relative(&app)).join("db")).unwrap(),
+            b"rows"
+        );
+
+        // Or back where they came from
+        std::fs::remove_file(app.join("db")).unwrap();
+        restore(None);
+        assert_eq!(std::fs::read(app.join("db")).unwrap(), b"rows");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dynamic_targets_expand_per_match() {
+        let dir = std::env::temp_dir().join("but-next-test-backup-dynamic");
+        let _ = std::fs::remove_dir_all(&dir);
+        for user in ["alice", "bob"] {
+            std::fs::create_dir_all(dir.join("home").join(user)).unwrap();
+            std::fs::write(dir.join("home").join(user).join(".profile"), user).unwrap();
+        }
+        std::fs::write(dir.join("home/README"), b"not a directory").unwrap();
+        let repo = LocalBackend::new(dir.join("repo"));
+        let config = Config {
+            settings: Settings::default(),
+            backup: BTreeMap::from([(
+                "homes".to_string(),
+                BackupTarget {
+                    from: vec![dir.join("home/*")],
+                    retention: Some(RetentionPolicy {
+                        keep_last: Some(2),
+                        ..RetentionPolicy::default()
+                    }),
+                    ..BackupTarget::default()
+                },
+            )]),
+        };
+
+        let run = backup_all(&config, &repo, &BackupOptions::default()).unwrap();
+        let names: Vec<&str> = run
+            .snapshots
+            .iter()
+            .map(|s| s.target_name.as_str())
+            .collect();
+        assert_eq!(names, ["homes/alice", "homes/bob"]);
+        assert_eq!(run.snapshots[0].source_path, dir.join("home/alice"));
+
+        // One sub-target, by name; history and retention are its own
+        let run =
+            backup_targets(&config, &repo, &["homes/bob"], &BackupOptions::default()).unwrap();
+        assert_eq!(run.snapshots.len(), 1);
+        assert_eq!(config.retention_for("homes/bob").keep_last, Some(2));
+        assert!(config.select_targets("homes/carol").is_empty());
+        assert!(config.configured("homes/carol").is_some());
+        assert!(config.configured("docs").is_none());
+        // Brackets only count in a glob, where `[[]` escapes them
+        let bracketed = BackupTarget {
+            from: vec![dir.join("photos [2023]")],
+            ..BackupTarget::default()
+        };
+        assert!(!bracketed.is_dynamic());
+        std::fs::create_dir_all(dir.join("[old]/carol")).unwrap();
+        let escaped = BackupTarget {
+            from: vec![dir.join("[[]old]/*")],
+            ..BackupTarget::default()
+        };
+        let names: Vec<String> = escaped.expand("old").into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["old/carol"]);
+
+        assert!(config.selects("homes", "homes/carol"));
+        assert!(config.selects("homes/bob", "homes/bob"));
+        assert!(!config.selects("homes/bob", "homes/bobby"));
+        assert!(!config.selects("home", "homes/bob"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
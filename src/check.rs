@@ -0,0 +1,697 @@
+//! # Repository Check & Repair
+//!
+//! Audits the whole repository rather than a single snapshot: every manifest
+//! is parsed, every blob is decoded and re-hashed against its name, and every
+//! file entry is checked for a dangling blob reference.
+//!
+//! `repair` acts on the findings of a check:
+//!
+//! 1. Blobs with bit rot are rebuilt from their Reed-Solomon parity
+//! 2. Unparseable manifests, corrupted blobs and stray objects are moved into
+//!    `quarantine/` (never deleted, so nothing is lost if the check was wrong)
+//! 3. Snapshots referencing missing or corrupted blobs are rewritten with the
+//!    affected entries marked `damaged`, so restore can skip them
+//!
+//! With `--from-source`, repair first walks the configured source directories
+//! and re-stores any blob whose content is still present unchanged on disk,
+//! clearing the `damaged` flag on entries it heals. No new snapshot is written.
+
+use crate::backend::Backend;
+use crate::compress;
+use crate::config::{CompressionKind, Config};
+use crate::crypto;
+use crate::error::{RepoError, Result};
+use crate::hasher;
+use crate::manifest::{self, Snapshot};
+use crate::parity::{self, ParityStatus};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use walkdir::WalkDir;
+
+/// A file entry whose blob is missing or corrupted.
+#[derive(Debug, Clone)]
+pub struct DanglingRef {
+    pub snapshot_id: String,
+    pub path: String,
+    pub hash: String,
+}
+
+/// Findings of a full repository check.
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// Number of manifests that parsed successfully.
+    pub snapshots_checked: usize,
+
+    /// Number of blobs decoded and re-hashed.
+    pub blobs_checked: usize,
+
+    /// Blobs that could not be verified (encrypted, no password given).
+    pub blobs_unverified: usize,
+
+    /// Manifests that failed to parse, with the parse error.
+    pub corrupted_manifests: Vec<manifest::CorruptedManifest>,
+
+    /// Blobs whose decoded content does not hash to their name.
+    pub corrupted_blobs: BTreeSet<String>,
+
+    /// Damaged or missing blobs that can be rebuilt from parity.
+    pub recoverable_blobs: BTreeSet<String>,
+
+    /// Blobs referenced by a manifest but absent from the store.
+    pub missing_blobs: BTreeSet<String>,
+
+    /// File entries pointing at missing or corrupted blobs.
+    pub dangling: Vec<DanglingRef>,
+
+    /// File entries already marked damaged by a previous repair.
+    pub damaged: Vec<DanglingRef>,
+
+    /// Blobs present in the store but not referenced by any snapshot.
+    pub unreferenced_blobs: Vec<String>,
+
+    /// Keys in the blob store that do not follow the sharded layout.
+    pub stray_files: Vec<String>,
+}
+
+impl CheckReport {
+    /// Returns true if the check found nothing that needs repairing.
+    ///
+    /// Unreferenced blobs are harmless (they are reclaimed by prune) and do
+    /// not count as problems.
+    pub fn is_healthy(&self) -> bool {
+        self.problem_count() == 0
+    }
+
+    /// Total number of problems found.
+    pub fn problem_count(&self) -> usize {
+        self.corrupted_manifests.len()
+            + self.recoverable_blobs.len()
+            + self.corrupted_blobs.len()
+            + self.missing_blobs.len()
+            + self.stray_files.len()
+    }
+
+    /// Converts an unhealthy report into a [`RepoError::Corrupted`].
+    pub fn ensure_healthy(&self) -> Result<()> {
+        if self.is_healthy() {
+            return Ok(());
+        }
+        Err(RepoError::Corrupted {
+            message: format!(
+                "{} problem(s) found ({} manifest(s), {} recoverable blob(s), {} corrupted blob(s), {} missing blob(s), {} stray file(s)); run `but-next repair`",
+                self.problem_count(),
+                self.corrupted_manifests.len(),
+                self.recoverable_blobs.len(),
+                self.corrupted_blobs.len(),
+                self.missing_blobs.len(),
+                self.stray_files.len(),
+            ),
+        }
+        .into())
+    }
+
+    /// Prints every finding followed by a one-line summary.
+    pub fn print(&self) {
+        for (key, e) in &self.corrupted_manifests {
+            eprintln!(
+                "  {} corrupted manifest: {key} ({e})",
+                colored::Colorize::red("✗"),
+            );
+        }
+        for hash in &self.recoverable_blobs {
+            eprintln!(
+                "  {} damaged blob (recoverable from parity): {}",
+                colored::Colorize::yellow("!"),
+                hasher::short_hash(hash, 12),
+            );
+        }
+        for hash in &self.corrupted_blobs {
+            eprintln!(
+                "  {} corrupted blob: {}",
+                colored::Colorize::red("✗"),
+                hasher::short_hash(hash, 12),
+            );
+        }
+        for hash in &self.missing_blobs {
+            eprintln!(
+                "  {} missing blob: {}",
+                colored::Colorize::red("✗"),
+                hasher::short_hash(hash, 12),
+            );
+        }
+        for r in &self.dangling {
+            eprintln!(
+                "  {} {}: {} → {}",
+                colored::Colorize::yellow("!"),
+                r.snapshot_id,
+                r.path,
+                hasher::short_hash(&r.hash, 12),
+            );
+        }
+        for key in &self.stray_files {
+            eprintln!("  {} stray file: {key}", colored::Colorize::yellow("!"));
+        }
+
+        eprintln!();
+        eprintln!(
+            "  Checked {} snapshot(s), {} blob(s){}",
+            self.snapshots_checked,
+            self.blobs_checked,
+            if self.blobs_unverified > 0 {
+                format!(
+                    " ({} encrypted blob(s) not verified, no password)",
+                    self.blobs_unverified
+                )
+            } else {
+                String::new()
+            },
+        );
+        if !self.damaged.is_empty() {
+            eprintln!("  {} file(s) previously marked damaged", self.damaged.len());
+        }
+        if !self.unreferenced_blobs.is_empty() {
+            eprintln!("  {} unreferenced blob(s)", self.unreferenced_blobs.len());
+        }
+    }
+}
+
+/// Outcome of a repair run.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub manifests_quarantined: usize,
+    pub blobs_quarantined: usize,
+    pub stray_quarantined: usize,
+    pub blobs_rebuilt: usize,
+    pub snapshots_rewritten: usize,
+    pub files_marked_damaged: usize,
+}
+
+/// Outcome of healing blobs from the live source directories.
+#[derive(Debug, Default)]
+pub struct HealReport {
+    /// Files hashed while searching for lost content.
+    pub files_scanned: u64,
+
+    /// Blobs re-stored from matching source files.
+    pub blobs_healed: usize,
+
+    /// Blobs that could not be healed because they need a password.
+    pub blobs_locked: usize,
+
+    /// Previously damaged entries whose blob is now whole again.
+    pub files_recovered: usize,
+
+    /// Manifests rewritten to clear `damaged` flags.
+    pub snapshots_rewritten: usize,
+}
+
+/// How a lost blob has to be re-encoded to match its manifest.
+struct LostBlob {
+    size: u64,
+    compression: CompressionKind,
+    encrypted: bool,
+}
+
+/// Audits every manifest and blob in the repository.
+///
+/// Encrypted blobs can only be verified when `password` is given; otherwise
+/// they are checked for presence only and counted as unverified.
+pub fn check_repository(repo: &dyn Backend, password: Option<&str>) -> Result<CheckReport> {
+    let mut report = CheckReport::default();
+
+    let (snapshots, corrupted) = manifest::scan_snapshots(repo)?;
+    report.snapshots_checked = snapshots.len();
+    report.corrupted_manifests = corrupted;
+
+    // How each referenced blob was encoded, taken from the first snapshot using it
+    let mut encodings: HashMap<&str, (CompressionKind, bool)> = HashMap::new();
+    for snap in &snapshots {
+        for entry in snap.files.values() {
+            encodings
+                .entry(entry.hash.as_str())
+                .or_insert((snap.compression, snap.encrypted));
+        }
+    }
+
+    let (present, stray) = manifest::list_blobs(repo)?;
+    report.stray_files = stray;
+
+    let pb = create_check_progress(present.len() as u64);
+    for hash in &present {
+        pb.inc(1);
+        if parity::inspect(repo, hash)? == ParityStatus::Recoverable {
+            report.recoverable_blobs.insert(hash.clone());
+        }
+        let Some(&(compression, encrypted)) = encodings.get(hash.as_str()) else {
+            report.unreferenced_blobs.push(hash.clone());
+            continue;
+        };
+        if encrypted && password.is_none() {
+            report.blobs_unverified += 1;
+            continue;
+        }
+
+        report.blobs_checked += 1;
+        // Only a blob that's gone counts as damaged, not a failed request
+        let data = match manifest::read_blob(repo, hash) {
+            Ok(data) => Some(data),
+            Err(e) if manifest::is_missing(&e) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let intact = data
+            .and_then(|data| decode_blob(&data, compression, encrypted, password).ok())
+            .is_some_and(|data| hasher::hash_bytes(&data) == *hash);
+        if !intact {
+            report.corrupted_blobs.insert(hash.clone());
+        }
+    }
+    pb.finish_and_clear();
+
+    let present: BTreeSet<&str> = present.iter().map(String::as_str).collect();
+    for snap in &snapshots {
+        for (path, entry) in &snap.files {
+            if entry.damaged {
+                report.damaged.push(DanglingRef {
+                    snapshot_id: snap.id.clone(),
+                    path: path.clone(),
+                    hash: entry.hash.clone(),
+                });
+                continue;
+            }
+            let mut missing = !present.contains(entry.hash.as_str());
+            if missing
+                && (report.recoverable_blobs.contains(&entry.hash)
+                    || parity::inspect(repo, &entry.hash)? == ParityStatus::Recoverable)
+            {
+                report.recoverable_blobs.insert(entry.hash.clone());
+                missing = false;
+            }
+            if missing {
+                report.missing_blobs.insert(entry.hash.clone());
+            }
+            if missing || report.corrupted_blobs.contains(&entry.hash) {
+                report.dangling.push(DanglingRef {
+                    snapshot_id: snap.id.clone(),
+                    path: path.clone(),
+                    hash: entry.hash.clone(),
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Repairs the problems found by [`check_repository`].
+pub fn repair_repository(
+    repo: &dyn Backend,
+    report: &CheckReport,
+    verbose: bool,
+) -> Result<RepairReport> {
+    let mut result = RepairReport::default();
+
+    for hash in &report.recoverable_blobs {
+        if parity::restore_blob(repo, hash)? {
+            result.blobs_rebuilt += 1;
+        }
+    }
+
+    for (key, _) in &report.corrupted_manifests {
+        let dest = manifest::quarantine(repo, key)?;
+        if verbose {
+            eprintln!("  {} {dest}", colored::Colorize::yellow("→"));
+        }
+        result.manifests_quarantined += 1;
+    }
+
+    for hash in &report.corrupted_blobs {
+        let dest = manifest::quarantine(repo, &manifest::blob_key(hash))?;
+        if verbose {
+            eprintln!("  {} {dest}", colored::Colorize::yellow("→"));
+        }
+        result.blobs_quarantined += 1;
+    }
+
+    for key in &report.stray_files {
+        manifest::quarantine(repo, key)?;
+        result.stray_quarantined += 1;
+    }
+
+    // Group dangling references by snapshot so each manifest is rewritten once
+    let mut by_snapshot: HashMap<&str, Vec<&DanglingRef>> = HashMap::new();
+    for r in &report.dangling {
+        by_snapshot
+            .entry(r.snapshot_id.as_str())
+            .or_default()
+            .push(r);
+    }
+
+    if !by_snapshot.is_empty() {
+        for mut snap in manifest::list_snapshots(repo)? {
+            let Some(refs) = by_snapshot.get(snap.id.as_str()) else {
+                continue;
+            };
+            let marked = mark_damaged(&mut snap, refs);
+            if marked > 0 {
+                manifest::save_snapshot(repo, &snap)?;
+                result.snapshots_rewritten += 1;
+                result.files_marked_damaged += marked;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Re-stores missing, corrupted and damaged blobs from the live source tree.
+///
+/// Walks the `from` path of every target with an affected snapshot, hashing
+/// only files whose size matches a lost blob. A match is encoded exactly as
+/// its manifest expects (compression, encryption) and written back; entries
+/// referencing healed blobs have their `damaged` flag cleared.
+pub fn heal_from_source(
+    config: &Config,
+    repo: &dyn Backend,
+    report: &CheckReport,
+    password: Option<&str>,
+    verbose: bool,
+) -> Result<HealReport> {
+    let mut result = HealReport::default();
+
+    let lost_refs: Vec<&DanglingRef> = report.dangling.iter().chain(&report.damaged).collect();
+    if lost_refs.is_empty() {
+        return Ok(result);
+    }
+    let lost_hashes: HashSet<&str> = lost_refs.iter().map(|r| r.hash.as_str()).collect();
+    let affected_ids: HashSet<&str> = lost_refs.iter().map(|r| r.snapshot_id.as_str()).collect();
+
+    let snapshots = manifest::list_snapshots(repo)?;
+    let mut lost: HashMap<String, LostBlob> = HashMap::new();
+    let mut targets = BTreeSet::new();
+    for snap in snapshots
+        .iter()
+        .filter(|s| affected_ids.contains(s.id.as_str()))
+    {
+        targets.insert(snap.target_name.as_str());
+        for entry in snap.files.values() {
+            if lost_hashes.contains(entry.hash.as_str()) {
+                lost.entry(entry.hash.clone()).or_insert(LostBlob {
+                    size: entry.size,
+                    compression: snap.compression,
+                    encrypted: snap.encrypted,
+                });
+            }
+        }
+    }
+
+    if password.is_none() {
+        let before = lost.len();
+        lost.retain(|_, blob| !blob.encrypted);
+        result.blobs_locked = before - lost.len();
+    }
+    let sizes: HashSet<u64> = lost.values().map(|blob| blob.size).collect();
+
+    let mut healed = HashSet::new();
+    for (_, target) in targets.iter().flat_map(|name| config.select_targets(name)) {
+        for entry in target
+            .from
+            .iter()
+            .filter(|source| source.exists())
+            .flat_map(WalkDir::new)
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+        {
+            if healed.len() == lost.len() {
+                break;
+            }
+            let path = entry.path();
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !sizes.contains(&metadata.len()) {
+                continue;
+            }
+
+            // Hash the bytes that get stored: the file may change while
+            // it's read, and a blob must match the hash it's stored under
+            result.files_scanned += 1;
+            let Ok(raw_data) = std::fs::read(path) else {
+                continue;
+            };
+            let hash = hasher::hash_bytes(&raw_data);
+            let Some(blob) = lost.get(&hash) else {
+                continue;
+            };
+            if healed.contains(&hash) {
+                continue;
+            }
+
+            let mut data =
+                compress::compress(&raw_data, blob.compression, config.settings.zstd_level)?;
+            if blob.encrypted {
+                if let Some(pw) = password {
+                    data = crypto::encrypt(&data, pw)?;
+                }
+            }
+
+            // Keep the corrupted copy around rather than overwriting it
+            if report.corrupted_blobs.contains(&hash) && manifest::blob_exists(repo, &hash)? {
+                manifest::quarantine(repo, &manifest::blob_key(&hash))?;
+            }
+            manifest::store_blob(repo, &hash, &data)?;
+            if config.settings.parity > 0 {
+                parity::store_parity(repo, &hash, &data, config.settings.parity)?;
+            } else {
+                // Parity of the lost copy no longer matches the re-encoded blob
+                let _ = repo.delete(&manifest::parity_key(&hash));
+            }
+
+            if verbose {
+                eprintln!(
+                    "  {} {} ← {}",
+                    colored::Colorize::green("+"),
+                    hasher::short_hash(&hash, 12),
+                    path.display(),
+                );
+            }
+            healed.insert(hash);
+        }
+    }
+    result.blobs_healed = healed.len();
+
+    // Clear damaged flags on entries whose blob is whole again
+    for mut snap in snapshots {
+        let mut recovered = 0;
+        for entry in snap.files.values_mut() {
+            if entry.damaged && healed.contains(&entry.hash) {
+                entry.damaged = false;
+                recovered += 1;
+            }
+        }
+        if recovered > 0 {
+            manifest::save_snapshot(repo, &snap)?;
+            result.snapshots_rewritten += 1;
+            result.files_recovered += recovered;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Marks the given entries of `snapshot` as damaged, returning how many changed.
+fn mark_damaged(snapshot: &mut Snapshot, refs: &[&DanglingRef]) -> usize {
+    let mut marked = 0;
+    for r in refs {
+        if let Some(entry) = snapshot.files.get_mut(&r.path) {
+            if !entry.damaged {
+                entry.damaged = true;
+                marked += 1;
+            }
+        }
+    }
+    marked
+}
+
+/// Reverses the blob encoding: optional decryption, then decompression.
+fn decode_blob(
+    data: &[u8],
+    compression: CompressionKind,
+    encrypted: bool,
+    password: Option<&str>,
+) -> Result<Vec<u8>> {
+    let compressed = match (encrypted, password) {
+        (true, Some(pw)) => crypto::decrypt(data, pw)?,
+        (true, None) => {
+            return Err(anyhow::anyhow!("blob is encrypted but no password provided").into())
+        }
+        (false, _) => data.to_vec(),
+    };
+    compress::decompress(&compressed, compression)
+}
+
+fn create_check_progress(total: u64) -> ProgressBar {
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("  {spinner:.green} Checking blobs [{bar:30.cyan/dim}] {pos}/{len}")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("━╸─"),
+    );
+    pb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::LocalBackend;
+    use crate::manifest::FileEntry;
+    use std::path::PathBuf;
+
+    fn temp_repo(name: &str) -> (PathBuf, LocalBackend) {
+        let dir = std::env::temp_dir().join(format!("but-next-test-check-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        (dir.clone(), LocalBackend::new(dir))
+    }
+
+    fn add_blob(repo: &dyn Backend, snap: &mut Snapshot, path: &str, content: &[u8]) -> String {
+        let hash = hasher::hash_bytes(content);
+        let data = compress::compress(content, CompressionKind::Zstd, 3).unwrap();
+        manifest::store_blob(repo, &hash, &data).unwrap();
+        snap.add_file(
+            path.to_string(),
+            FileEntry {
+                hash: hash.clone(),
+                size: content.len() as u64,
+                stored_size: data.len() as u64,
+                permissions: None,
+                modified: 0,
+                deduplicated: false,
+                damaged: false,
+            },
+        );
+        hash
+    }
+
+    #[test]
+    fn healthy_repository_passes() {
+        let (dir, repo) = temp_repo("healthy");
+        let mut snap = Snapshot::new("t", PathBuf::from("/src"), CompressionKind::Zstd, false);
+        add_blob(&repo, &mut snap, "a.txt", b"alpha");
+        manifest::save_snapshot(&repo, &snap).unwrap();
+
+        let report = check_repository(&repo, None).unwrap();
+        assert!(report.is_healthy());
+        assert_eq!(report.blobs_checked, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_and_repairs_damage() {
+        let (dir, repo) = temp_repo("damage");
+        let mut snap = Snapshot::new("t", PathBuf::from("/src"), CompressionKind::Zstd, false);
+        let bad = add_blob(&repo, &mut snap, "bad.txt", b"will be corrupted");
+        let gone = add_blob(&repo, &mut snap, "gone.txt", b"will be deleted");
+        add_blob(&repo, &mut snap, "ok.txt", b"stays intact");
+        manifest::save_snapshot(&repo, &snap).unwrap();
+        repo.write("snapshots/broken.json", b"{ nope").unwrap();
+
+        repo.write(&manifest::blob_key(&bad), b"garbage").unwrap();
+        repo.delete(&manifest::blob_key(&gone)).unwrap();
+
+        let report = check_repository(&repo, None).unwrap();
+        assert_eq!(report.corrupted_manifests.len(), 1);
+        assert!(report.corrupted_blobs.contains(&bad));
+        assert!(report.missing_blobs.contains(&gone));
+        assert_eq!(report.dangling.len(), 2);
+        assert!(report.ensure_healthy().is_err());
+
+        let repaired = repair_repository(&repo, &report, false).unwrap();
+        assert_eq!(repaired.manifests_quarantined, 1);
+        assert_eq!(repaired.blobs_quarantined, 1);
+        assert_eq!(repaired.files_marked_damaged, 2);
+        assert!(repo.exists("quarantine/snapshots/broken.json").unwrap());
+
+        let snap = manifest::resolve_snapshot(&repo, &snap.id).unwrap();
+        assert!(snap.files["bad.txt"].damaged);
+        assert!(snap.files["gone.txt"].damaged);
+        assert!(!snap.files["ok.txt"].damaged);
+
+        let recheck = check_repository(&repo, None).unwrap();
+        assert!(recheck.is_healthy());
+        assert_eq!(recheck.damaged.len(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn heals_damaged_blobs_from_source() {
+        let (dir, repo) = temp_repo("heal");
+        let source = std::env::temp_dir().join("but-next-test-check-heal-src");
+        let _ = std::fs::remove_dir_all(&source);
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), b"still on disk").unwrap();
+
+        let mut snap = Snapshot::new("t", source.clone(), CompressionKind::Zstd, false);
+        let hash = add_blob(&repo, &mut snap, "a.txt", b"still on disk");
+        add_blob(&repo, &mut snap, "b.txt", b"gone from disk too");
+        manifest::save_snapshot(&repo, &snap).unwrap();
+        for object in repo.list("blobs/").unwrap() {
+            repo.delete(&object.key).unwrap();
+        }
+
+        let report = check_repository(&repo, None).unwrap();
+        repair_repository(&repo, &report, false).unwrap();
+
+        let config: Config = toml::from_str(&format!(
+            "[settings]\nrepo_path = {:?}\n[backup.t]\nfrom = {:?}\n",
+            dir, source
+        ))
+        .unwrap();
+        let report = check_repository(&repo, None).unwrap();
+        let healed = heal_from_source(&config, &repo, &report, None, false).unwrap();
+        assert_eq!(healed.blobs_healed, 1);
+        assert_eq!(healed.files_recovered, 1);
+
+        let snap = manifest::resolve_snapshot(&repo, &snap.id).unwrap();
+        assert!(!snap.files["a.txt"].damaged);
+        assert!(snap.files["b.txt"].damaged);
+        assert!(manifest::blob_exists(&repo, &hash).unwrap());
+        assert!(check_repository(&repo, None).unwrap().is_healthy());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&source).unwrap();
+    }
+
+    #[test]
+    fn rebuilds_bit_rot_from_parity() {
+        let (dir, repo) = temp_repo("parity");
+        let mut snap = Snapshot::new("t", PathBuf::from("/src"), CompressionKind::Zstd, false);
+        // Incompressible content so the stored blob spans several shards
+        let content: Vec<u8> = (0..20_000u64)
+            .map(|i| (i.wrapping_mul(6364136223846793005) >> 56) as u8)
+            .collect();
+        let hash = add_blob(&repo, &mut snap, "a.bin", &content);
+        manifest::save_snapshot(&repo, &snap).unwrap();
+        let stored = manifest::read_blob(&repo, &hash).unwrap();
+        parity::store_parity(&repo, &hash, &stored, 25).unwrap();
+
+        let mut rotten = stored.clone();
+        rotten[100] ^= 0xff;
+        repo.write(&manifest::blob_key(&hash), &rotten).unwrap();
+
+        // Reads are corrected transparently before repair touches anything
+        assert_eq!(manifest::read_blob(&repo, &hash).unwrap(), stored);
+
+        let report = check_repository(&repo, None).unwrap();
+        assert!(report.recoverable_blobs.contains(&hash));
+        assert!(report.corrupted_blobs.is_empty());
+
+        let repaired = repair_repository(&repo, &report, false).unwrap();
+        assert_eq!(repaired.blobs_rebuilt, 1);
+        assert_eq!(repo.read(&manifest::blob_key(&hash)).unwrap(), stored);
+        assert!(check_repository(&repo, None).unwrap().is_healthy());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
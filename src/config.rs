@@ -4,10 +4,13 @@
 //! Searches multiple standard locations with a well-defined priority order,
 //! then validates all paths and settings before returning.
 
+use crate::daemon;
 use crate::error::{ConfigError, Result};
+use crate::filter;
+use crate::retention::{self, RetentionPolicy};
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 use std::{env, fs};
 
 /// Top-level configuration structure.
@@ -20,7 +23,7 @@ pub struct Config {
 /// Global settings controlling backup behavior.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Settings {
-    /// Interval between backup cycles in seconds (used in watch mode).
+    /// Watch mode: polling period, and the longest a detected change waits for a backup (seconds).
     #[serde(default = "default_interval")]
     pub interval: u64,
 
@@ -40,20 +43,162 @@ pub struct Settings {
     #[serde(default)]
     pub encrypt: bool,
 
+    /// Reed-Solomon parity redundancy as a percentage of each blob (0 = disabled).
+    #[serde(default)]
+    pub parity: u8,
+
     /// Maximum number of snapshots to retain per target (0 = unlimited).
     #[serde(default)]
     pub max_snapshots: usize,
 
+    /// Default retention rules applied by `prune`.
+    #[serde(default, skip_serializing_if = "RetentionPolicy::is_empty")]
+    pub retention: RetentionPolicy,
+
+    /// Prune each target according to its retention rules after backing it up.
+    #[serde(default)]
+    pub auto_prune: bool,
+
+    /// Skip the snapshot when no file changed since the target's previous one.
+    /// When unset, `watch` skips and `backup` records the unchanged snapshot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_unchanged: Option<bool>,
+
     /// Repository root directory for content-addressable blob storage.
     #[serde(default = "default_repo_path")]
     pub repo_path: PathBuf,
+
+    /// Repository URL selecting the storage backend (e.g. `local:/srv/backup`).
+    /// Takes precedence over `repo_path` when set.
+    #[serde(default)]
+    pub repository: Option<String>,
+
+    /// Second repository URL that every `backup` run copies new snapshots to.
+    #[serde(default)]
+    pub mirror: Option<String>,
+
+    /// Options for `s3:` repositories.
+    #[serde(default)]
+    pub s3: Option<S3Settings>,
+
+    /// Options for `exec:` repositories.
+    #[serde(default)]
+    pub exec: Option<ExecSettings>,
+
+    /// Job schedules for `daemon`.
+    #[serde(default)]
+    pub daemon: Option<DaemonSettings>,
 }
 
-/// A single backup target mapping a source directory to a destination.
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            interval: default_interval(),
+            filename: default_filename(),
+            compression: default_compression(),
+            zstd_level: default_zstd_level(),
+            encrypt: false,
+            parity: 0,
+            max_snapshots: 0,
+            retention: RetentionPolicy::default(),
+            auto_prune: false,
+            skip_unchanged: None,
+            repo_path: default_repo_path(),
+            repository: None,
+            mirror: None,
+            s3: None,
+            exec: None,
+            daemon: None,
+        }
+    }
+}
+
+/// Connection options for S3-compatible object storage.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct S3Settings {
+    /// Signing region (`us-east-1` for most non-AWS services).
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+
+    /// Access key; falls back to `AWS_ACCESS_KEY_ID`.
+    pub access_key_id: Option<String>,
+
+    /// Secret key; falls back to `AWS_SECRET_ACCESS_KEY`.
+    pub secret_access_key: Option<String>,
+
+    /// Address buckets as `host/bucket` instead of `bucket.host` (needed for MinIO).
+    #[serde(default = "default_true")]
+    pub path_style: bool,
+
+    /// Multipart upload part size in MiB; larger objects are split (minimum 5).
+    #[serde(default = "default_part_size_mib")]
+    pub part_size_mib: u64,
+
+    /// Cache manifests locally so listing doesn't re-download them.
+    #[serde(default = "default_true")]
+    pub cache: bool,
+
+    /// Manifest cache directory (default: `~/.cache/but-next/s3/...`).
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for S3Settings {
+    fn default() -> Self {
+        Self {
+            region: default_s3_region(),
+            access_key_id: None,
+            secret_access_key: None,
+            path_style: true,
+            part_size_mib: default_part_size_mib(),
+            cache: true,
+            cache_dir: None,
+        }
+    }
+}
+
+/// Launch options for an external storage helper.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ExecSettings {
+    /// Arguments passed to the helper program.
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Extra environment variables for the helper.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// Schedules for the jobs `daemon` runs besides backups.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DaemonSettings {
+    /// Cron schedule for applying retention rules to every target.
+    #[serde(default)]
+    pub prune: Option<String>,
+
+    /// Cron schedule for auditing the repository (as `check` does).
+    #[serde(default)]
+    pub check: Option<String>,
+
+    /// Where last-run times are kept
+    /// (default: `$XDG_STATE_HOME/but-next/daemon.json`).
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+}
+
+/// A single backup target mapping a source directory to a destination.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct BackupTarget {
-    /// Source directory to back up.
-    pub from: PathBuf,
+    /// Source directories to back up: a single path, or a list of absolute
+    /// paths walked together into one snapshot. A single path with a `*` or
+    /// `?` wildcard (`/home/*`) makes the target dynamic: each directory it
+    /// matches at backup time is backed up as a sub-target of its own.
+    /// `[...]` classes only apply in such a path, so other paths may contain
+    /// brackets; in a glob, `[[]` matches a literal `[`.
+    #[serde(
+        deserialize_with = "sources::deserialize",
+        serialize_with = "sources::serialize"
+    )]
+    pub from: Vec<PathBuf>,
 
     /// Destination directory for archives (used in archive mode).
     #[serde(default = "default_dest")]
@@ -62,9 +207,55 @@ pub struct BackupTarget {
     /// Optional per-target compression override.
     pub compression: Option<CompressionKind>,
 
-    /// Glob patterns to exclude from backup.
+    /// Patterns (`.gitignore` syntax) of files to back up; empty means all.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Patterns (`.gitignore` syntax) of files and directories to skip.
     #[serde(default)]
     pub exclude: Vec<String>,
+
+    /// Also honor `.gitignore` files in the source, like `.butignore` files.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub gitignore: bool,
+
+    /// Skip directories tagged as caches by a `CACHEDIR.TAG` file.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub exclude_caches: bool,
+
+    /// Skip directories containing any of these files (e.g. `.nobackup`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_if_present: Vec<String>,
+
+    /// Skip files larger than this (e.g. `4GiB`, `500M`, or plain bytes).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<String>,
+
+    /// Skip files smaller than this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_file_size: Option<String>,
+
+    /// Only back up files modified within this long before the backup
+    /// (e.g. `5y`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub newer_than: Option<String>,
+
+    /// Only back up files left unmodified for at least this long (e.g. `1h`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub older_than: Option<String>,
+
+    /// Don't descend into directories on other filesystems (mount points).
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub one_file_system: bool,
+
+    /// Retention rules overriding `[settings.retention]` for this target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+
+    /// Cron schedule (`0 * * * *`, `@daily`, ...) on which `daemon` backs
+    /// up this target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
 }
 
 /// Supported compression backends.
@@ -104,6 +295,152 @@ fn default_repo_path() -> PathBuf {
 fn default_dest() -> PathBuf {
     PathBuf::from("./")
 }
+fn default_true() -> bool {
+    true
+}
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+fn default_part_size_mib() -> u64 {
+    16
+}
+
+impl BackupTarget {
+    /// Whether `from` is a glob expanded into sub-targets.
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self.from.as_slice(), [source] if is_glob(source))
+    }
+
+    /// One sub-target per directory the glob in `from` matches, named after
+    /// the part of its path the wildcards matched (`/home/*` →
+    /// `{name}/alice`). Not dynamic targets are returned as they are.
+    pub fn expand(&self, name: &str) -> Vec<(String, BackupTarget)> {
+        if !self.is_dynamic() {
+            return vec![(name.to_string(), self.clone())];
+        }
+        let pattern = &self.from[0];
+        let literal: PathBuf = pattern
+            .components()
+            .map_while(|c| unescape(&c.as_os_str().to_string_lossy()))
+            .collect();
+        let Ok(paths) = glob::glob(&pattern.to_string_lossy()) else {
+            return Vec::new();
+        };
+        paths
+            .filter_map(|path| path.ok())
+            .filter(|path| path.is_dir())
+            .map(|path| {
+                let matched = source_prefix(path.strip_prefix(&literal).unwrap_or(&path));
+                let target = BackupTarget {
+                    from: vec![path],
+                    ..self.clone()
+                };
+                (format!("{name}/{matched}"), target)
+            })
+            .collect()
+    }
+
+    /// Each source with the path its files are stored under in a snapshot:
+    /// none for a single source, otherwise the source's absolute path without
+    /// its root (`/var/lib/app` → `var/lib/app`). Validation ensures several
+    /// sources share their root, so dropping it can't make them collide.
+    pub fn sources(&self) -> Vec<(&Path, String)> {
+        match self.from.as_slice() {
+            [source] => vec![(source.as_path(), String::new())],
+            sources => sources
+                .iter()
+                .map(|source| (source.as_path(), source_prefix(source)))
+                .collect(),
+        }
+    }
+
+    /// Directory the paths in a snapshot are relative to: the source itself,
+    /// or the filesystem root for several sources.
+    pub fn source_path(&self) -> PathBuf {
+        match self.from.as_slice() {
+            [source] => source.clone(),
+            sources => sources
+                .first()
+                .and_then(|source| source.ancestors().last())
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// The sources, comma-separated.
+    pub fn display_sources(&self) -> String {
+        self.from
+            .iter()
+            .map(|source| source.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Whether `path` has a wildcard. Brackets alone don't count, so a literal
+/// directory such as `/data/photos [2023]` isn't taken for a glob.
+fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?'])
+}
+
+/// A glob path component with its `[x]` escapes resolved, or `None` if it
+/// can match more than one name.
+fn unescape(component: &str) -> Option<String> {
+    let mut literal = String::new();
+    let mut chars = component.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' | '?' => return None,
+            '[' => match (chars.next()?, chars.next()?) {
+                (escaped, ']') if escaped != '!' => literal.push(escaped),
+                _ => return None,
+            },
+            c => literal.push(c),
+        }
+    }
+    Some(literal)
+}
+
+fn source_prefix(path: &Path) -> String {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// `from` is written as a plain path when there is only one.
+mod sources {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::PathBuf;
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<PathBuf>, D::Error> {
+        Ok(match OneOrMany::deserialize(d)? {
+            OneOrMany::One(path) => vec![path],
+            OneOrMany::Many(paths) => paths,
+        })
+    }
+
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], s: S) -> Result<S::Ok, S::Error> {
+        match paths {
+            [path] => path.serialize(s),
+            paths => paths.serialize(s),
+        }
+    }
+}
 
 /// Standard configuration file search paths, in descending priority order.
 fn config_search_paths() -> Vec<PathBuf> {
@@ -120,15 +457,16 @@ fn config_search_paths() -> Vec<PathBuf> {
 
 /// Loads configuration from the first found config file in the search path.
 pub fn load_config() -> Result<Config> {
-    let search = config_search_paths();
+    load_config_from(&find_config()?)
+}
 
-    for path in &search {
-        if path.exists() {
-            return load_config_from(path);
-        }
+/// Returns the first config file found in the search path.
+pub fn find_config() -> Result<PathBuf> {
+    let search = config_search_paths();
+    match search.iter().find(|path| path.exists()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(ConfigError::NotFound { searched: search }.into()),
     }
-
-    Err(ConfigError::NotFound { searched: search }.into())
 }
 
 /// Loads and validates configuration from a specific file path.
@@ -165,17 +503,224 @@ fn validate_config(config: &Config) -> std::result::Result<(), ConfigError> {
         });
     }
 
+    if config.settings.parity > 100 {
+        return Err(ConfigError::Validation {
+            message: format!(
+                "parity must be a percentage between 0 and 100, got {}",
+                config.settings.parity
+            ),
+        });
+    }
+
+    if let Some(within) = &config.settings.retention.keep_within {
+        validate_duration(within, "settings.retention.keep_within")?;
+    }
+
     for (name, target) in &config.backup {
-        if target.from.as_os_str().is_empty() {
+        if name.contains(['~', '@', ':']) {
+            return Err(ConfigError::Validation {
+                message: format!(
+                    "backup target '{name}' can't contain '~', '@' or ':', \
+                     which snapshot selectors use"
+                ),
+            });
+        }
+        if target.from.is_empty() || target.from.iter().any(|p| p.as_os_str().is_empty()) {
             return Err(ConfigError::Validation {
                 message: format!("backup target '{name}' has empty 'from' path"),
             });
         }
+        if target.from.len() > 1 {
+            validate_sources(&target.from, &format!("backup.{name}.from"))?;
+        }
+        if target.from.iter().any(|source| is_glob(source)) {
+            validate_glob(name, &target.from)?;
+        }
+        if let Some(within) = target
+            .retention
+            .as_ref()
+            .and_then(|r| r.keep_within.as_ref())
+        {
+            validate_duration(within, &format!("backup.{name}.retention.keep_within"))?;
+        }
+        if let Some(schedule) = &target.schedule {
+            validate_schedule(schedule, &format!("backup.{name}.schedule"))?;
+        }
+        for (field, patterns) in [("include", &target.include), ("exclude", &target.exclude)] {
+            filter::validate_patterns(patterns).map_err(|e| ConfigError::Validation {
+                message: format!("backup.{name}.{field}: {e}"),
+            })?;
+        }
+        for (field, size) in [
+            ("max_file_size", &target.max_file_size),
+            ("min_file_size", &target.min_file_size),
+        ] {
+            if let Some(size) = size {
+                filter::parse_size(size).map_err(|e| ConfigError::Validation {
+                    message: format!("backup.{name}.{field}: {e}"),
+                })?;
+            }
+        }
+        for (field, age) in [
+            ("newer_than", &target.newer_than),
+            ("older_than", &target.older_than),
+        ] {
+            if let Some(age) = age {
+                filter::age_cutoff(chrono::Utc::now(), age).map_err(|e| {
+                    ConfigError::Validation {
+                        message: format!("backup.{name}.{field}: {e}"),
+                    }
+                })?;
+            }
+        }
+    }
+
+    if let Some(daemon) = &config.settings.daemon {
+        for (field, schedule) in [("prune", &daemon.prune), ("check", &daemon.check)] {
+            if let Some(schedule) = schedule {
+                validate_schedule(schedule, &format!("settings.daemon.{field}"))?;
+            }
+        }
     }
 
     Ok(())
 }
 
+/// Checks that `value` parses and can be subtracted from the current time.
+fn validate_duration(value: &str, field: &str) -> std::result::Result<(), ConfigError> {
+    retention::subtract(chrono::Utc::now(), value)
+        .map(|_| ())
+        .map_err(|e| ConfigError::Validation {
+            message: format!("{field}: {e}"),
+        })
+}
+
+/// Several sources must be absolute and disjoint, as each one's files are
+/// stored under its own path.
+fn validate_sources(sources: &[PathBuf], field: &str) -> std::result::Result<(), ConfigError> {
+    let invalid = |message: String| ConfigError::Validation {
+        message: format!("{field}: {message}"),
+    };
+    // Paths in the snapshot are relative to one root, so all sources must
+    // share it (on Windows, the same drive)
+    let root = |path: &Path| path.ancestors().last().map(Path::to_path_buf);
+    for (i, source) in sources.iter().enumerate() {
+        if !source.is_absolute() {
+            return Err(invalid(format!(
+                "'{}' must be an absolute path when several sources are given",
+                source.display()
+            )));
+        }
+        if root(source) != root(&sources[0]) {
+            return Err(invalid(format!(
+                "'{}' and '{}' are on different drives",
+                sources[0].display(),
+                source.display()
+            )));
+        }
+        if let Some(other) = sources[..i]
+            .iter()
+            .find(|other| source.starts_with(other) || other.starts_with(source))
+        {
+            return Err(invalid(format!(
+                "sources '{}' and '{}' overlap",
+                other.display(),
+                source.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_glob(name: &str, sources: &[PathBuf]) -> std::result::Result<(), ConfigError> {
+    let invalid = |message: String| ConfigError::Validation {
+        message: format!("backup.{name}.from: {message}"),
+    };
+    if name.contains('/') {
+        return Err(ConfigError::Validation {
+            message: format!(
+                "backup target '{name}' has a glob source, so its name can't contain '/'"
+            ),
+        });
+    }
+    let [pattern] = sources else {
+        return Err(invalid(
+            "a glob can't be combined with other sources".to_string(),
+        ));
+    };
+    glob::Pattern::new(&pattern.to_string_lossy())
+        .map(drop)
+        .map_err(|e| invalid(format!("invalid glob '{}': {e}", pattern.display())))
+}
+
+fn validate_schedule(value: &str, field: &str) -> std::result::Result<(), ConfigError> {
+    daemon::parse_schedule(value)
+        .map(|_| ())
+        .map_err(|e| ConfigError::Validation {
+            message: format!("{field}: {e}"),
+        })
+}
+
+impl Config {
+    /// The configured target `name` refers to: the target itself, or the
+    /// dynamic target a sub-target (`homes/alice`) was expanded from.
+    pub fn configured(&self, name: &str) -> Option<&BackupTarget> {
+        self.backup.get(name).or_else(|| {
+            let (parent, _) = name.split_once('/')?;
+            self.backup.get(parent).filter(|t| t.is_dynamic())
+        })
+    }
+
+    /// Returns true if `name`, as given to `--target`, selects snapshots of
+    /// `target`: the same target, or any sub-target of a dynamic target,
+    /// including those whose directory no longer matches.
+    pub fn selects(&self, name: &str, target: &str) -> bool {
+        target == name
+            || self.backup.get(name).is_some_and(BackupTarget::is_dynamic)
+                && target
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    /// Every target to back up, with dynamic targets expanded as of now.
+    pub fn expand_targets(&self) -> Vec<(String, BackupTarget)> {
+        self.backup
+            .iter()
+            .flat_map(|(name, target)| target.expand(name))
+            .collect()
+    }
+
+    /// The expanded targets `name` selects: itself, or all sub-targets of a
+    /// dynamic target.
+    pub fn select_targets(&self, name: &str) -> Vec<(String, BackupTarget)> {
+        match self.configured(name) {
+            Some(target) if target.is_dynamic() && self.backup.contains_key(name) => {
+                target.expand(name)
+            }
+            Some(target) if target.is_dynamic() => {
+                let (parent, _) = name.split_once('/').unwrap_or((name, ""));
+                target
+                    .expand(parent)
+                    .into_iter()
+                    .filter(|(sub, _)| sub == name)
+                    .collect()
+            }
+            Some(target) => vec![(name.to_string(), target.clone())],
+            None => Vec::new(),
+        }
+    }
+
+    /// Effective retention rules for a target: its own rules layered over
+    /// the global ones. Sub-targets use the rules of their dynamic target;
+    /// targets not in the config use the global rules.
+    pub fn retention_for(&self, target: &str) -> RetentionPolicy {
+        match self.configured(target).and_then(|t| t.retention.as_ref()) {
+            Some(policy) => policy.overlay(&self.settings.retention),
+            None => self.settings.retention.clone(),
+        }
+    }
+}
+
 /// Generates a default configuration file at the given path.
 pub fn init_config(path: &Path) -> Result<()> {
     if path.exists() {
@@ -189,23 +734,40 @@ pub fn init_config(path: &Path) -> Result<()> {
             compression: CompressionKind::Zstd,
             zstd_level: 3,
             encrypt: false,
+            parity: 0,
             max_snapshots: 0,
+            retention: RetentionPolicy {
+                keep_daily: Some(7),
+                keep_weekly: Some(4),
+                keep_monthly: Some(12),
+                ..RetentionPolicy::default()
+            },
+            auto_prune: false,
+            skip_unchanged: None,
             repo_path: PathBuf::from(".but"),
+            repository: None,
+            mirror: None,
+            s3: None,
+            exec: None,
+            daemon: None,
         },
         backup: BTreeMap::from([
             (
                 "documents".to_string(),
                 BackupTarget {
-                    from: PathBuf::from("/home/user/Documents"),
+                    from: vec![PathBuf::from("/home/user/Documents")],
                     dest: PathBuf::from("/backup/documents"),
                     compression: None,
                     exclude: vec!["*.tmp".to_string(), "*.cache".to_string()],
+                    retention: None,
+                    schedule: Some("0 * * * *".to_string()),
+                    ..BackupTarget::default()
                 },
             ),
             (
                 "projects".to_string(),
                 BackupTarget {
-                    from: PathBuf::from("/home/user/Projects"),
+                    from: vec![PathBuf::from("/home/user/Projects")],
                     dest: PathBuf::from("/backup/projects"),
                     compression: Some(CompressionKind::Zstd),
                     exclude: vec![
@@ -213,6 +775,10 @@ pub fn init_config(path: &Path) -> Result<()> {
                         "node_modules/".to_string(),
                         ".git/".to_string(),
                     ],
+                    retention: None,
+                    schedule: Some("@daily".to_string()),
+                    exclude_caches: true,
+                    ..BackupTarget::default()
                 },
             ),
         ]),
@@ -239,3 +805,53 @@ pub fn expand_filename(template: &str, name: &str) -> String {
         .replace("%date%", &now.format("%Y%m%d").to_string())
         .replace("%time%", &now.format("%H%M%S").to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses and validates `[settings]` plus `targets`, returning the
+    /// validation error.
+    fn rejection(targets: &str) -> Option<String> {
+        let config: Config = toml::from_str(&format!("[settings]\n{targets}")).unwrap();
+        validate_config(&config).err().map(|e| e.to_string())
+    }
+
+    #[test]
+    fn target_names_must_be_selectable() {
+        assert!(rejection("[backup.docs]\nfrom = \"/docs\"\n").is_none());
+        for name in ["docs:old", "a~b", "me@home"] {
+            let error = rejection(&format!("[backup.\"{name}\"]\nfrom = \"/docs\"\n"));
+            assert!(error.is_some_and(|e| e.contains(name)), "{name} accepted");
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn invalid_sources_are_rejected() {
+        let target = |from: &str| format!("[backup.t]\nfrom = {from}\n");
+        assert!(rejection(&target(r#"["/etc", "/var/lib/app"]"#)).is_none());
+        assert!(rejection(&target(r#""/home/*""#)).is_none());
+
+        for (from, expected) in [
+            (r#"["/etc", "relative/app"]"#, "must be an absolute path"),
+            (r#"["/var", "/var/lib/app"]"#, "overlap"),
+            (r#"["/var/lib/app", "/var"]"#, "overlap"),
+            (r#"["/home/*", "/etc"]"#, "can't be combined"),
+            (r#""/home/[*""#, "invalid glob"),
+        ] {
+            let error = rejection(&target(from)).unwrap_or_default();
+            assert!(error.contains(expected), "{from}: got '{error}'");
+        }
+
+        let error = rejection("[backup.\"a/b\"]\nfrom = \"/home/*\"\n").unwrap_or_default();
+        assert!(error.contains("can't contain '/'"), "got '{error}'");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn sources_on_different_drives_are_rejected() {
+        let error = rejection("[backup.t]\nfrom = ['C:\\data', 'D:\\data']\n");
+        assert!(error.is_some_and(|e| e.contains("different drives")));
+    }
+}
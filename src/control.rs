@@ -0,0 +1,377 @@
+//! # Control Socket
+//!
+//! `but-next watch` listens on a Unix domain socket so that a running
+//! service can be inspected and steered with `but-next ctl`. Each connection
+//! carries one JSON request line and gets one JSON response line back:
+//!
+//! | Request                                   | Effect                                        |
+//! |-------------------------------------------|-----------------------------------------------|
+//! | `{"command":"status"}`                    | current backup, progress, last result per target |
+//! | `{"command":"trigger"}`                   | back up every target now                      |
+//! | `{"command":"trigger","target":"docs"}`   | back up one target (or dynamic target) now    |
+//! | `{"command":"pause"}`                     | record changes but start no backups           |
+//! | `{"command":"resume"}`                    | back up what changed while paused             |
+//! | `{"command":"shutdown"}`                  | stop as SIGTERM does                          |
+//!
+//! Triggered backups run even while paused; pausing doesn't interrupt a
+//! backup already running. The socket is only accessible to its owner.
+
+use crate::backup::{Progress, ProgressState};
+use crate::error::Result;
+use crate::service::Shutdown;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A request sent to the control socket.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Status,
+    Trigger {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        target: Option<String>,
+    },
+    Pause,
+    Resume,
+    Shutdown,
+}
+
+/// The answer to a [`Request`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "lowercase")]
+pub enum Response {
+    Status(Status),
+    Done { message: String },
+    Error { message: String },
+}
+
+/// What a running `watch` is doing.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    pub paused: bool,
+    /// The backup in progress, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<ProgressState>,
+    pub targets: BTreeMap<String, TargetStatus>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TargetStatus {
+    /// `events` or `polling`.
+    pub mode: String,
+    /// Changes are waiting to be backed up.
+    pub pending: bool,
+    /// Earliest retry after failed backups.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<LastRun>,
+}
+
+/// Outcome of a target's most recent backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LastRun {
+    pub finished_at: DateTime<Utc>,
+    pub ok: bool,
+    /// Snapshot created, unless nothing changed or the backup failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// State shared between the watch loop and the control socket.
+#[derive(Debug, Default)]
+pub struct Shared {
+    status: Mutex<Status>,
+    /// Targets to back up now; `None` means all of them.
+    triggers: Mutex<Vec<Option<String>>>,
+    pub progress: Progress,
+}
+
+impl Shared {
+    pub fn new() -> Self {
+        let shared = Self::default();
+        shared.status().pid = std::process::id();
+        shared
+    }
+
+    /// The status, for the watch loop to update.
+    pub fn status(&self) -> MutexGuard<'_, Status> {
+        self.status.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn paused(&self) -> bool {
+        self.status().paused
+    }
+
+    /// Takes the triggers received since the last call.
+    pub fn take_triggers(&self) -> Vec<Option<String>> {
+        std::mem::take(&mut *self.triggers.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Answers a request. A shutdown is only acknowledged here; the caller
+    /// requests it once the answer is sent, as the process may then exit.
+    fn handle(&self, request: &Request) -> Response {
+        let done = |message: &str| Response::Done {
+            message: message.to_string(),
+        };
+        match request {
+            Request::Status => {
+                let mut status = self.status().clone();
+                let progress = self.progress.get();
+                status.current = progress.target.is_some().then_some(progress);
+                Response::Status(status)
+            }
+            Request::Trigger { target } => {
+                if let Some(name) = target {
+                    // A dynamic target's name stands for all its sub-targets
+                    let known = self.status().targets.keys().any(|t| {
+                        t.strip_prefix(name.as_str())
+                            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                    });
+                    if !known {
+                        return Response::Error {
+                            message: format!("no such target: {name}"),
+                        };
+                    }
+                }
+                let message = match target {
+                    Some(name) => format!("backup of {name} triggered"),
+                    None => "backup of all targets triggered".to_string(),
+                };
+                self.triggers
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(target.clone());
+                Response::Done { message }
+            }
+            Request::Pause => {
+                self.status().paused = true;
+                done("paused")
+            }
+            Request::Resume => {
+                self.status().paused = false;
+                done("resumed")
+            }
+            Request::Shutdown => done("stopping after the current file"),
+        }
+    }
+}
+
+/// Default socket path: `$XDG_RUNTIME_DIR/but-next/control.sock`, or next to
+/// the daemon's state file.
+pub fn default_socket() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => Path::new(&dir).join("but-next").join("control.sock"),
+        None => crate::daemon::default_state_file().with_file_name("control.sock"),
+    }
+}
+
+/// A listening control socket; the socket file is removed when dropped.
+#[derive(Debug)]
+pub struct Listener {
+    path: PathBuf,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::time::Duration;
+
+    /// Longest a client may take to send its request.
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn listen(path: &Path, shared: Arc<Shared>, shutdown: Shutdown) -> Result<Listener> {
+        let parent = path
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)?;
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow::anyhow!(
+                    "{} is in use; is another watch running? (use --socket)",
+                    path.display()
+                )
+                .into());
+            }
+            // Left behind by a process that didn't exit cleanly
+            std::fs::remove_file(path)?;
+        }
+
+        // Bind inside a private directory and move the socket into place
+        // once restricted, so it is never reachable by other users
+        let staging = parent.join(format!(".control-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&staging);
+        std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+        let staged = staging.join("control.sock");
+        let bound = UnixListener::bind(&staged).and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+            std::fs::rename(&staged, path)?;
+            Ok(listener)
+        });
+        let _ = std::fs::remove_dir_all(&staging);
+        let listener =
+            bound.map_err(|e| anyhow::anyhow!("failed to listen on {}: {e}", path.display()))?;
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = serve_client(stream, &shared, &shutdown);
+            }
+        });
+        Ok(Listener {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn serve_client(
+        stream: UnixStream,
+        shared: &Shared,
+        shutdown: &Shutdown,
+    ) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let request: Option<Request> = serde_json::from_str(&line).ok();
+        let response = match &request {
+            Some(request) => shared.handle(request),
+            None => Response::Error {
+                message: format!("invalid request: {}", line.trim()),
+            },
+        };
+        let mut stream = stream;
+        serde_json::to_writer(&mut stream, &response)?;
+        stream.write_all(b"\n")?;
+        stream.flush()?;
+
+        if request == Some(Request::Shutdown) {
+            shutdown.request();
+        }
+        Ok(())
+    }
+
+    pub fn send(path: &Path, request: &Request) -> Result<Response> {
+        let mut stream = UnixStream::connect(path).map_err(|e| {
+            anyhow::anyhow!(
+                "can't connect to {}: {e}; is `but-next watch` running?",
+                path.display()
+            )
+        })?;
+        serde_json::to_writer(&mut stream, request).map_err(anyhow::Error::from)?;
+        stream.write_all(b"\n")?;
+
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let response = serde_json::from_str(&line)
+            .map_err(|e| anyhow::anyhow!("invalid response from {}: {e}", path.display()))?;
+        Ok(response)
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use super::*;
+
+    pub fn listen(_path: &Path, _shared: Arc<Shared>, _shutdown: Shutdown) -> Result<Listener> {
+        Err(anyhow::anyhow!("the control socket needs a Unix platform").into())
+    }
+
+    pub fn send(_path: &Path, _request: &Request) -> Result<Response> {
+        Err(anyhow::anyhow!("the control socket needs a Unix platform").into())
+    }
+}
+
+/// Starts answering requests on `path` in a background thread.
+pub fn listen(path: &Path, shared: Arc<Shared>, shutdown: Shutdown) -> Result<Listener> {
+    imp::listen(path, shared, shutdown)
+}
+
+/// Sends one request to the socket at `path` and waits for the response.
+pub fn send(path: &Path, request: &Request) -> Result<Response> {
+    imp::send(path, request)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn requests_round_trip_through_socket() {
+        let dir = std::env::temp_dir().join("but-next-test-control");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("control.sock");
+
+        let shared = Arc::new(Shared::new());
+        shared
+            .status()
+            .targets
+            .insert("docs".to_string(), TargetStatus::default());
+        shared
+            .status()
+            .targets
+            .insert("homes/alice".to_string(), TargetStatus::default());
+        let shutdown = Shutdown::default();
+        let listener = listen(&path, Arc::clone(&shared), shutdown.clone()).unwrap();
+        assert!(listen(&path, Arc::clone(&shared), shutdown.clone()).is_err());
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let trigger = |target: Option<&str>| {
+            send(
+                &path,
+                &Request::Trigger {
+                    target: target.map(String::from),
+                },
+            )
+            .unwrap()
+        };
+        assert!(matches!(trigger(Some("docs")), Response::Done { .. }));
+        assert!(matches!(trigger(Some("photos")), Response::Error { .. }));
+        assert!(matches!(trigger(Some("homes")), Response::Done { .. }));
+        assert!(matches!(trigger(Some("home")), Response::Error { .. }));
+        assert!(matches!(trigger(None), Response::Done { .. }));
+        assert_eq!(
+            shared.take_triggers(),
+            vec![Some("docs".to_string()), Some("homes".to_string()), None]
+        );
+
+        send(&path, &Request::Pause).unwrap();
+        match send(&path, &Request::Status).unwrap() {
+            Response::Status(status) => {
+                assert!(status.paused);
+                assert_eq!(status.pid, std::process::id());
+                assert!(status.targets.contains_key("docs"));
+            }
+            other => panic!("unexpected response: {other:?}"),
+        }
+
+        // Requested only once the answer is sent
+        send(&path, &Request::Shutdown).unwrap();
+        shutdown.sleep(std::time::Duration::from_secs(5));
+        assert!(shutdown.requested());
+
+        drop(listener);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
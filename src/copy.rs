@@ -0,0 +1,257 @@
+//! # Snapshot Copy
+//!
+//! Transfers snapshots between repositories, e.g. to keep an offsite mirror
+//! for the 3-2-1 rule. Blobs are content-addressed by the hash of the
+//! original file, so a blob already present in the destination never needs
+//! to be transferred again, whichever snapshot stored it.
+//!
+//! Blobs are copied byte for byte unless the destination uses a different
+//! password, in which case encrypted blobs are decrypted with the source
+//! key and re-encrypted with the destination key on the way.
+
+use crate::backend::Backend;
+use crate::crypto;
+use crate::error::Result;
+use crate::manifest::{self, Snapshot};
+use crate::parity;
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Options for [`copy_snapshots`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyOptions<'a> {
+    /// Password of the source repository.
+    pub password: Option<&'a str>,
+
+    /// Password for the destination; `None` keeps the source key.
+    pub dest_password: Option<&'a str>,
+
+    /// Parity redundancy for re-encrypted blobs (byte-for-byte copies keep
+    /// whatever parity the source has).
+    pub parity: u8,
+
+    pub verbose: bool,
+}
+
+/// Outcome of a copy.
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    pub snapshots_copied: usize,
+    /// Snapshots whose manifest already existed in the destination.
+    pub snapshots_present: usize,
+    pub blobs_copied: u64,
+    /// Blobs already present in the destination.
+    pub blobs_present: u64,
+    pub blobs_reencrypted: u64,
+    pub bytes_copied: u64,
+}
+
+/// Copies `snapshots` from `src` to `dest`, transferring only missing blobs.
+///
+/// Each manifest is written after all of its blobs, so an interrupted copy
+/// never leaves a snapshot in the destination that references missing data.
+pub fn copy_snapshots(
+    src: &dyn Backend,
+    dest: &dyn Backend,
+    snapshots: &[Snapshot],
+    opts: &CopyOptions,
+) -> Result<CopyReport> {
+    let mut report = CopyReport::default();
+    let reencrypt = opts
+        .dest_password
+        .is_some_and(|dest_pw| opts.password != Some(dest_pw));
+
+    for snapshot in snapshots {
+        if dest.exists(&manifest::snapshot_key(&snapshot.id))? {
+            report.snapshots_present += 1;
+            continue;
+        }
+        if snapshot.encrypted && reencrypt && opts.password.is_none() {
+            return Err(anyhow::anyhow!(
+                "snapshot {} is encrypted; the source password is needed to re-encrypt it",
+                snapshot.id
+            )
+            .into());
+        }
+
+        let pb = create_copy_progress(snapshot.files.len() as u64, &snapshot.id);
+        let mut copied = snapshot.clone();
+        for (path, entry) in &mut copied.files {
+            pb.inc(1);
+            if entry.damaged || manifest::blob_exists(dest, &entry.hash)? {
+                report.blobs_present += 1;
+                continue;
+            }
+
+            let data = manifest::read_blob(src, &entry.hash)?;
+            if snapshot.encrypted && reencrypt {
+                let plain = crypto::decrypt(&data, opts.password.unwrap_or_default())?;
+                let data = crypto::encrypt(&plain, opts.dest_password.unwrap_or_default())?;
+                manifest::store_blob(dest, &entry.hash, &data)?;
+                if opts.parity > 0 {
+                    parity::store_parity(dest, &entry.hash, &data, opts.parity)?;
+                }
+                entry.stored_size = data.len() as u64;
+                report.blobs_reencrypted += 1;
+                report.bytes_copied += data.len() as u64;
+            } else {
+                manifest::store_blob(dest, &entry.hash, &data)?;
+                if let Some(parity) = parity::read_parity(src, &entry.hash)? {
+                    dest.write(&manifest::parity_key(&entry.hash), &parity)?;
+                    report.bytes_copied += parity.len() as u64;
+                }
+                report.bytes_copied += data.len() as u64;
+            }
+            report.blobs_copied += 1;
+
+            if opts.verbose {
+                pb.suspend(|| eprintln!("  {} {path}", colored::Colorize::green("  +")));
+            }
+        }
+        pb.finish_and_clear();
+
+        // Without its parent in the destination, an unchanged snapshot keeps
+        // its own list of files
+        if let Some(parent) = copied.parent.as_deref().filter(|_| copied.unchanged) {
+            if !dest.exists(&manifest::snapshot_key(parent))? {
+                copied.unchanged = false;
+            }
+        }
+        manifest::save_snapshot(dest, &copied)?;
+        report.snapshots_copied += 1;
+    }
+
+    Ok(report)
+}
+
+fn create_copy_progress(total: u64, snapshot_id: &str) -> ProgressBar {
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&format!(
+                "  {{spinner:.green}} {snapshot_id} [{{bar:30.cyan/dim}}] {{pos}}/{{len}}"
+            ))
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("━╸─"),
+    );
+    pb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::LocalBackend;
+    use crate::config::CompressionKind;
+    use crate::manifest::FileEntry;
+    use std::path::PathBuf;
+
+    fn temp_repo(name: &str) -> (PathBuf, LocalBackend) {
+        let dir = std::env::temp_dir().join(format!("but-next-test-copy-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        (dir.clone(), LocalBackend::new(dir))
+    }
+
+    fn snapshot_with(
+        repo: &dyn Backend,
+        target: &str,
+        files: &[(&str, &[u8])],
+        password: Option<&str>,
+    ) -> Snapshot {
+        let mut snap = Snapshot::new(
+            target,
+            PathBuf::from("/src"),
+            CompressionKind::None,
+            password.is_some(),
+        );
+        for (path, content) in files {
+            let hash = crate::hasher::hash_bytes(content);
+            let data = match password {
+                Some(pw) => crypto::encrypt(content, pw).unwrap(),
+                None => content.to_vec(),
+            };
+            manifest::store_blob(repo, &hash, &data).unwrap();
+            snap.add_file(
+                path.to_string(),
+                FileEntry {
+                    hash,
+                    size: content.len() as u64,
+                    stored_size: data.len() as u64,
+                    permissions: None,
+                    modified: 0,
+                    deduplicated: false,
+                    damaged: false,
+                },
+            );
+        }
+        manifest::save_snapshot(repo, &snap).unwrap();
+        snap
+    }
+
+    #[test]
+    fn copies_only_missing_blobs() {
+        let (src_dir, src) = temp_repo("src");
+        let (dest_dir, dest) = temp_repo("dest");
+        let first = snapshot_with(&src, "docs", &[("a", b"alpha"), ("b", b"beta")], None);
+        let report = copy_snapshots(
+            &src,
+            &dest,
+            std::slice::from_ref(&first),
+            &CopyOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.snapshots_copied, 1);
+        assert_eq!(report.blobs_copied, 2);
+
+        let mut second = snapshot_with(&src, "docs", &[("a", b"alpha"), ("c", b"gamma")], None);
+        second.id.push_str("-2");
+        manifest::save_snapshot(&src, &second).unwrap();
+        let report = copy_snapshots(
+            &src,
+            &dest,
+            &[first, second.clone()],
+            &CopyOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(report.snapshots_present, 1);
+        assert_eq!(report.snapshots_copied, 1);
+        assert_eq!(report.blobs_copied, 1);
+        assert_eq!(report.blobs_present, 1);
+
+        let copied = manifest::resolve_snapshot(&dest, &second.id).unwrap();
+        assert_eq!(copied.files.len(), 2);
+        for entry in copied.files.values() {
+            assert!(manifest::blob_exists(&dest, &entry.hash).unwrap());
+        }
+
+        std::fs::remove_dir_all(&src_dir).unwrap();
+        std::fs::remove_dir_all(&dest_dir).unwrap();
+    }
+
+    #[test]
+    fn reencrypts_for_different_destination_key() {
+        let (src_dir, src) = temp_repo("enc-src");
+        let (dest_dir, dest) = temp_repo("enc-dest");
+        let snap = snapshot_with(&src, "docs", &[("a", b"secret data")], Some("old"));
+
+        let opts = CopyOptions {
+            dest_password: Some("new"),
+            ..CopyOptions::default()
+        };
+        assert!(copy_snapshots(&src, &dest, std::slice::from_ref(&snap), &opts).is_err());
+
+        let opts = CopyOptions {
+            password: Some("old"),
+            dest_password: Some("new"),
+            ..CopyOptions::default()
+        };
+        let report = copy_snapshots(&src, &dest, std::slice::from_ref(&snap), &opts).unwrap();
+        assert_eq!(report.blobs_reencrypted, 1);
+
+        let hash = &snap.files["a"].hash;
+        let stored = manifest::read_blob(&dest, hash).unwrap();
+        assert!(crypto::decrypt(&stored, "old").is_err());
+        assert_eq!(crypto::decrypt(&stored, "new").unwrap(), b"secret data");
+
+        std::fs::remove_dir_all(&src_dir).unwrap();
+        std::fs::remove_dir_all(&dest_dir).unwrap();
+    }
+}
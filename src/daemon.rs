@@ -0,0 +1,501 @@
+//! # Scheduled Daemon
+//!
+//! `but-next daemon` runs jobs on cron schedules:
+//!
+//! | Job             | Schedule                     | Runs                                   |
+//! |-----------------|------------------------------|----------------------------------------|
+//! | `backup:<name>` | the target's `schedule`      | a backup (plus auto-prune and mirror)  |
+//! | `prune`         | `[settings.daemon] prune`    | every target's retention rules         |
+//! | `check`         | `[settings.daemon] check`    | a repository audit, as `check` does    |
+//!
+//! Schedules are five-field cron expressions in local time (`30 2 * * 1-5`)
+//! or nicknames such as `@hourly`, `@daily` and `@weekly`.
+//!
+//! When each job last ran is kept in a state file. A job whose scheduled
+//! time passed while the daemon wasn't running, or while the machine was
+//! asleep, runs as soon as that is noticed — once, however many runs were
+//! missed. A failed job is retried with [`Backoff`] (but no later than its
+//! next scheduled run), also after a restart.
+
+use crate::backend::Backend;
+use crate::backup::{self, BackupOptions};
+use crate::check;
+use crate::config::Config;
+use crate::error::{BackupError, ButError, Result};
+use crate::manifest;
+use crate::service::{Backoff, ConfigFile, Shutdown};
+use chrono::{DateTime, Local, Utc};
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longest single sleep, so that jumps of the wall clock (suspend, manual
+/// clock changes) are noticed within this delay.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Parses a cron expression or nickname.
+pub fn parse_schedule(expr: &str) -> anyhow::Result<Cron> {
+    expr.parse::<Cron>()
+        .map_err(|e| anyhow::anyhow!("invalid schedule '{expr}': {e}"))
+}
+
+/// Outcome of a job's most recent run.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRun {
+    pub last_run: DateTime<Utc>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Persisted last-run times, keyed by job name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default)]
+    pub jobs: BTreeMap<String, JobRun>,
+}
+
+impl State {
+    /// Loads the state file; a missing or unreadable file means no job has run.
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                eprintln!(
+                    "warning: ignoring unreadable state file {}: {e}",
+                    path.display()
+                );
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Writes the state file atomically.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// What a job does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum JobKind {
+    Backup(String),
+    Prune,
+    Check,
+}
+
+#[derive(Debug)]
+struct Job {
+    name: String,
+    kind: JobKind,
+    schedule: Cron,
+    next: DateTime<Local>,
+    backoff: Backoff,
+}
+
+/// The next time a job is due: the first scheduled time after its last run,
+/// or after `started` if it never ran. A failed last run is retried right
+/// away. Times in the past mean "run now".
+pub fn next_due(
+    schedule: &Cron,
+    last: Option<&JobRun>,
+    started: DateTime<Local>,
+) -> anyhow::Result<DateTime<Local>> {
+    let from = match last {
+        Some(last) if !last.ok => return Ok(started),
+        Some(last) => last.last_run.with_timezone(&Local),
+        None => started,
+    };
+    schedule
+        .find_next_occurrence(&from, false)
+        .map_err(|e| anyhow::anyhow!("schedule has no next occurrence: {e}"))
+}
+
+/// Default location of the state file.
+pub fn default_state_file() -> PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")));
+    match base {
+        Some(base) => base.join("but-next").join("daemon.json"),
+        None => PathBuf::from("but-next-daemon.json"),
+    }
+}
+
+fn state_file(config: &Config) -> PathBuf {
+    config
+        .settings
+        .daemon
+        .as_ref()
+        .and_then(|d| d.state_file.clone())
+        .unwrap_or_else(default_state_file)
+}
+
+/// Builds the job list of `config`. Jobs carried over from `previous` with
+/// the same schedule keep their next run and backoff.
+fn schedule_jobs(
+    config: &Config,
+    state: &State,
+    started: DateTime<Local>,
+    previous: &[Job],
+) -> Result<Vec<Job>> {
+    let settings = config.settings.daemon.clone().unwrap_or_default();
+    let mut schedules = Vec::new();
+    for (name, target) in &config.backup {
+        match &target.schedule {
+            Some(expr) => schedules.push((
+                format!("backup:{name}"),
+                JobKind::Backup(name.clone()),
+                expr.clone(),
+            )),
+            None => eprintln!(
+                "  {} {name}: no schedule, not backed up by the daemon",
+                colored::Colorize::yellow("!"),
+            ),
+        }
+    }
+    if let Some(expr) = settings.prune {
+        schedules.push(("prune".to_string(), JobKind::Prune, expr));
+    }
+    if let Some(expr) = settings.check {
+        schedules.push(("check".to_string(), JobKind::Check, expr));
+    }
+    if schedules.is_empty() {
+        return Err(anyhow::anyhow!(
+            "nothing to schedule: set `schedule` on a target or [settings.daemon] prune/check"
+        )
+        .into());
+    }
+
+    let mut jobs = Vec::new();
+    for (name, kind, expr) in schedules {
+        let schedule = parse_schedule(&expr)?;
+        let carried = previous
+            .iter()
+            .find(|j| j.name == name && j.schedule.as_str() == schedule.as_str());
+        let (next, backoff) = match carried {
+            Some(job) => (job.next, job.backoff),
+            None => match next_due(&schedule, state.jobs.get(&name), started) {
+                Ok(next) => (next, Backoff::default()),
+                Err(e) => {
+                    warn_unscheduled(&name, &e);
+                    continue;
+                }
+            },
+        };
+        jobs.push(Job {
+            name,
+            kind,
+            schedule,
+            next,
+            backoff,
+        });
+    }
+    Ok(jobs)
+}
+
+/// Reports a job dropped because its schedule has no further occurrence.
+fn warn_unscheduled(name: &str, error: &anyhow::Error) {
+    eprintln!(
+        "  {} {name}: {error}; not run again",
+        colored::Colorize::yellow("!"),
+    );
+}
+
+fn print_jobs(jobs: &[Job], state: &State, now: DateTime<Local>) {
+    eprintln!();
+    for job in jobs {
+        let last = match state.jobs.get(&job.name) {
+            Some(run) => format!(
+                "{}{}",
+                run.last_run.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                if run.ok { "" } else { " (failed)" },
+            ),
+            None => "never".to_string(),
+        };
+        let next = if job.next <= now {
+            match state.jobs.get(&job.name) {
+                Some(run) if !run.ok => "now (retry)".to_string(),
+                _ => "now (missed)".to_string(),
+            }
+        } else {
+            job.next.format("%Y-%m-%d %H:%M").to_string()
+        };
+        eprintln!(
+            "  {:20}  {:14}  last {:25}  next {}",
+            job.name,
+            job.schedule.as_str(),
+            last,
+            next,
+        );
+    }
+}
+
+/// Runs scheduled jobs until SIGTERM or SIGINT, reloading the configuration
+/// from `config_path` whenever it changes.
+pub fn run(config_path: PathBuf, opts: &BackupOptions) -> Result<()> {
+    let shutdown = Shutdown::install()?;
+    let (mut file, mut config, mut repo) = ConfigFile::open(config_path)?;
+    let mut state_path = state_file(&config);
+    let mut state = State::load(&state_path);
+
+    eprintln!("  Repository: {}", repo.location());
+    eprintln!("  State: {}", state_path.display());
+    let started = Local::now();
+    let mut jobs = schedule_jobs(&config, &state, started, &[])?;
+    print_jobs(&jobs, &state, started);
+
+    while !shutdown.requested() {
+        if let Some((new_config, new_repo)) = file.reload() {
+            let new_path = state_file(&new_config);
+            let new_state = if new_path == state_path {
+                None
+            } else {
+                Some(State::load(&new_path))
+            };
+            let carried = schedule_jobs(
+                &new_config,
+                new_state.as_ref().unwrap_or(&state),
+                started,
+                &jobs,
+            );
+            match carried {
+                Ok(new_jobs) => {
+                    config = new_config;
+                    repo = new_repo;
+                    jobs = new_jobs;
+                    if let Some(new_state) = new_state {
+                        state = new_state;
+                        state_path = new_path;
+                    }
+                    print_jobs(&jobs, &state, Local::now());
+                }
+                Err(e) => eprintln!(
+                    "  {} Keeping the previous schedule: {e}",
+                    colored::Colorize::yellow("!"),
+                ),
+            }
+        }
+
+        let now = Local::now();
+        let opts = BackupOptions {
+            skip_unchanged: config.settings.skip_unchanged.unwrap_or(false),
+            cancel: Some(shutdown.flag()),
+            ..*opts
+        };
+        let mut ended = Vec::new();
+        for job in jobs.iter_mut().filter(|j| j.next <= now) {
+            let result = run_job(&config, repo.as_ref(), &opts, &job.kind);
+            if matches!(result, Err(ButError::Backup(BackupError::Interrupted))) {
+                // Not recorded, so it runs again on the next start
+                break;
+            }
+
+            let finished = Utc::now();
+            let next = next_due(&job.schedule, None, Local::now());
+            match (&result, next) {
+                (Ok(()), Ok(next)) => {
+                    job.backoff.reset();
+                    job.next = next;
+                }
+                (Ok(()), Err(e)) => {
+                    warn_unscheduled(&job.name, &e);
+                    ended.push(job.name.clone());
+                }
+                (Err(e), next) => {
+                    let delay = job.backoff.fail();
+                    let retry = Local::now() + chrono::Duration::seconds(delay.as_secs() as i64);
+                    job.next = next.map_or(retry, |next| retry.min(next));
+                    eprintln!(
+                        "  {} {} failed: {e}; retrying at {}",
+                        colored::Colorize::red("✗"),
+                        job.name,
+                        job.next.format("%H:%M:%S"),
+                    );
+                }
+            }
+
+            state.jobs.insert(
+                job.name.clone(),
+                JobRun {
+                    last_run: finished,
+                    ok: result.is_ok(),
+                    error: result.err().map(|e| e.to_string()),
+                },
+            );
+            if let Err(e) = state.save(&state_path) {
+                eprintln!(
+                    "  {} Failed to save {}: {e}",
+                    colored::Colorize::yellow("!"),
+                    state_path.display(),
+                );
+            }
+            if shutdown.requested() {
+                break;
+            }
+        }
+
+        jobs.retain(|job| !ended.contains(&job.name));
+
+        // With every job dropped, idle until a reload schedules new ones
+        let wait = match jobs.iter().map(|j| j.next).min() {
+            Some(next) => (next - Local::now()).to_std().unwrap_or_default(),
+            None => MAX_SLEEP,
+        };
+        shutdown.sleep(wait.min(MAX_SLEEP));
+    }
+
+    eprintln!("\n  {} Stopped", colored::Colorize::green("✓"));
+    Ok(())
+}
+
+fn run_job(
+    config: &Config,
+    repo: &dyn Backend,
+    opts: &BackupOptions,
+    kind: &JobKind,
+) -> Result<()> {
+    eprintln!(
+        "\n  {} {}",
+        colored::Colorize::dimmed("───"),
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+    );
+    match kind {
+        JobKind::Backup(name) => {
+            let mut run = backup::backup_targets(config, repo, &[name.as_str()], opts)?;
+            match run.failed.pop() {
+                Some((_, e)) => Err(e),
+                None => Ok(()),
+            }
+        }
+        JobKind::Prune => {
+            eprintln!(
+                "{} Pruning",
+                colored::Colorize::bold(colored::Colorize::cyan("▶")),
+            );
+            // Sub-targets of dynamic targets keep their history after their
+            // directory is gone, so the names come from the repository
+            let mut names: Vec<String> = manifest::list_snapshots(repo)?
+                .into_iter()
+                .map(|s| s.target_name)
+                .filter(|name| config.configured(name).is_some())
+                .collect();
+            names.sort();
+            names.dedup();
+            for name in &names {
+                let policy = config.retention_for(name);
+                if policy.is_empty() && config.settings.max_snapshots == 0 {
+                    continue;
+                }
+                let (deleted, freed) =
+                    backup::prune_snapshots(repo, name, &policy, config.settings.max_snapshots)?;
+                eprintln!(
+                    "  {name}: pruned {deleted} snapshot(s), freed {}",
+                    backup::format_size(freed),
+                );
+            }
+            Ok(())
+        }
+        JobKind::Check => {
+            eprintln!(
+                "{} Checking repository",
+                colored::Colorize::bold(colored::Colorize::cyan("▶")),
+            );
+            let report = check::check_repository(repo, opts.password)?;
+            report.print();
+            report.ensure_healthy()?;
+            eprintln!("  {} No problems found", colored::Colorize::green("✓"));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn missed_runs_are_caught_up_once() {
+        let hourly = parse_schedule("0 * * * *").unwrap();
+        let started = Local.with_ymd_and_hms(2024, 5, 1, 12, 30, 0).unwrap();
+
+        // Never ran: wait for the next slot
+        assert_eq!(
+            next_due(&hourly, None, started).unwrap(),
+            Local.with_ymd_and_hms(2024, 5, 1, 13, 0, 0).unwrap()
+        );
+
+        // Last ran hours before the daemon started: due immediately, and
+        // only the first missed slot counts
+        let mut last = JobRun {
+            last_run: Local
+                .with_ymd_and_hms(2024, 5, 1, 8, 10, 0)
+                .unwrap()
+                .with_timezone(&Utc),
+            ok: true,
+            error: None,
+        };
+        let due = next_due(&hourly, Some(&last), started).unwrap();
+        assert_eq!(due, Local.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap());
+        assert!(due <= started);
+
+        // A failed run is retried on start, whatever the schedule
+        last.last_run = started.with_timezone(&Utc);
+        last.ok = false;
+        assert_eq!(next_due(&hourly, Some(&last), started).unwrap(), started);
+
+        let never = parse_schedule("0 0 30 2 *").unwrap();
+        assert!(next_due(&never, None, started).is_err());
+        assert!(parse_schedule("@daily").is_ok());
+        assert!(parse_schedule("every hour").is_err());
+    }
+
+    #[test]
+    fn jobs_without_next_run_are_dropped() {
+        let config: Config = toml::from_str(
+            "[settings.daemon]\nprune = \"@daily\"\n\
+             [backup.docs]\nfrom = \"/docs\"\nschedule = \"0 0 30 2 *\"\n",
+        )
+        .unwrap();
+        let jobs = schedule_jobs(&config, &State::default(), Local::now(), &[]).unwrap();
+        let names: Vec<&str> = jobs.iter().map(|j| j.name.as_str()).collect();
+        assert_eq!(names, ["prune"]);
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let dir = std::env::temp_dir().join("but-next-test-daemon-state");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("nested").join("daemon.json");
+        assert!(State::load(&path).jobs.is_empty());
+
+        let mut state = State::default();
+        state.jobs.insert(
+            "backup:docs".to_string(),
+            JobRun {
+                last_run: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+                ok: false,
+                error: Some("source directory does not exist".to_string()),
+            },
+        );
+        state.save(&path).unwrap();
+
+        let loaded = State::load(&path);
+        assert_eq!(
+            loaded.jobs["backup:docs"].last_run,
+            state.jobs["backup:docs"].last_run
+        );
+        assert!(!loaded.jobs["backup:docs"].ok);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -25,6 +25,9 @@ pub enum ButError {
     #[error("encryption error: {0}")]
     Crypto(#[from] CryptoError),
 
+    #[error("storage backend error: {0}")]
+    Backend(#[from] BackendError),
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -79,8 +82,11 @@ pub enum BackupError {
     #[error("failed to write manifest: {0}")]
     ManifestWrite(#[source] std::io::Error),
 
-    #[error("no changes detected since last snapshot")]
-    NothingChanged,
+    #[error("no changes detected since snapshot {0}")]
+    NothingChanged(String),
+
+    #[error("interrupted; no snapshot was written")]
+    Interrupted,
 }
 
 /// Errors during restoration.
@@ -124,6 +130,29 @@ pub enum RepoError {
     Locked(PathBuf),
 }
 
+/// Storage backend errors.
+#[derive(Error, Debug)]
+pub enum BackendError {
+    #[error("object not found: {0}")]
+    NotFound(String),
+
+    #[error("I/O error on {key}: {source}")]
+    Io {
+        key: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("unsupported repository scheme '{0}'")]
+    UnsupportedScheme(String),
+
+    #[error("invalid repository URL '{url}': {message}")]
+    InvalidUrl { url: String, message: String },
+
+    #[error("remote storage error: {message}")]
+    Remote { message: String },
+}
+
 /// Cryptographic operation errors.
 #[derive(Error, Debug)]
 #[allow(dead_code)]
@@ -0,0 +1,584 @@
+//! # File Selection
+//!
+//! Decides which files under a target's source are backed up. `exclude` and
+//! `include` patterns use `.gitignore` syntax, relative to the source:
+//!
+//! | Pattern          | Matches                                              |
+//! |------------------|------------------------------------------------------|
+//! | `*.tmp`          | any `.tmp` file, at any depth                        |
+//! | `build/`         | directories named `build`, at any depth              |
+//! | `/build`         | only `build` directly under the source (anchored)    |
+//! | `docs/*.tmp`     | `.tmp` files directly in `docs/` (a `/` anchors too) |
+//! | `**/build/*.o`   | `.o` files in any `build` directory                  |
+//! | `[abc]?.log`     | character classes and single-character wildcards     |
+//! | `!keep.log`      | re-includes what an earlier pattern excluded         |
+//!
+//! With `include` set, only files matching one of its patterns are
+//! considered, and `exclude` then removes from those. As with git, a file
+//! inside an excluded directory can't be re-included, since excluded
+//! directories aren't descended into.
+//!
+//! Inside the source, rules can also come from the tree itself:
+//!
+//! - a `.butignore` file adds patterns for its directory and everything
+//!   below, in the same syntax (`.gitignore` files too, with `gitignore`);
+//! - with `exclude_caches`, directories holding a valid `CACHEDIR.TAG`
+//!   (<https://bford.info/cachedir/>) are skipped;
+//! - directories holding any file named in `exclude_if_present` are skipped.
+//!
+//! These can exclude more than the configuration does, but never bring back
+//! what its `exclude` patterns remove.
+//!
+//! Files can also be limited by size (`max_file_size`, `min_file_size`) and
+//! by modification time relative to the start of the backup (`newer_than`,
+//! `older_than`), and `one_file_system` keeps the walk off other mounted
+//! filesystems. Files and mount points left out this way are counted in the
+//! snapshot's stats, unlike those matched by patterns.
+
+use crate::config::BackupTarget;
+use crate::error::Result;
+use crate::retention;
+use chrono::{DateTime, Utc};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{DirEntry, WalkBuilder};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Per-directory ignore file, read like a `.gitignore`.
+pub const IGNORE_FILE: &str = ".butignore";
+
+/// Header of a valid `CACHEDIR.TAG`.
+const CACHEDIR_SIGNATURE: &[u8] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// File selection rules of one target.
+#[derive(Debug, Clone)]
+pub struct FileFilter {
+    root: PathBuf,
+    include: Option<Gitignore>,
+    exclude: Gitignore,
+    gitignore: bool,
+    exclude_caches: bool,
+    markers: Vec<String>,
+    max_size: Option<u64>,
+    min_size: Option<u64>,
+    /// Files must be modified after this…
+    newer_than: Option<SystemTime>,
+    /// …and before this.
+    older_than: Option<SystemTime>,
+    one_file_system: bool,
+}
+
+impl FileFilter {
+    pub fn new(root: &Path, include: &[String], exclude: &[String]) -> Result<Self> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build(root, include)?)
+        };
+        Ok(Self {
+            root: root.to_path_buf(),
+            include,
+            exclude: build(root, exclude)?,
+            gitignore: false,
+            exclude_caches: false,
+            markers: Vec::new(),
+            max_size: None,
+            min_size: None,
+            newer_than: None,
+            older_than: None,
+            one_file_system: false,
+        })
+    }
+
+    /// The rules of `target` for one of its sources.
+    pub fn for_source(target: &BackupTarget, root: &Path) -> Result<Self> {
+        let size = |value: &Option<String>| value.as_deref().map(parse_size).transpose();
+        let now = Utc::now();
+        let cutoff =
+            |value: &Option<String>| value.as_deref().map(|age| age_cutoff(now, age)).transpose();
+        Ok(Self {
+            gitignore: target.gitignore,
+            exclude_caches: target.exclude_caches,
+            markers: target.exclude_if_present.clone(),
+            max_size: size(&target.max_file_size)?,
+            min_size: size(&target.min_file_size)?,
+            newer_than: cutoff(&target.newer_than)?,
+            older_than: cutoff(&target.older_than)?,
+            one_file_system: target.one_file_system,
+            ..Self::new(root, &target.include, &target.exclude)?
+        })
+    }
+
+    /// Whether `path`, under the root, is excluded on its own account
+    /// (its parent directories are not consulted).
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.exclude.matched(path, is_dir).is_ignore()
+    }
+
+    /// Whether the file at `path` matches the include patterns, if any.
+    pub fn is_included(&self, path: &Path) -> bool {
+        self.include.as_ref().map_or(true, |include| {
+            include.matched_path_or_any_parents(path, false).is_ignore()
+        })
+    }
+
+    /// Whether the directory at `dir` is tagged to be skipped.
+    pub fn is_marked(&self, dir: &Path) -> bool {
+        (self.exclude_caches && is_cache_dir(dir))
+            || self.markers.iter().any(|marker| dir.join(marker).exists())
+    }
+
+    /// Whether a file of this size and modification time is within the
+    /// size and age limits.
+    pub fn is_within_limits(&self, size: u64, modified: Option<SystemTime>) -> bool {
+        let aged = |cutoff: Option<SystemTime>, after: bool| {
+            cutoff.map_or(true, |cutoff| {
+                modified.is_some_and(|m| if after { m > cutoff } else { m < cutoff })
+            })
+        };
+        self.max_size.map_or(true, |max| size <= max)
+            && self.min_size.map_or(true, |min| size >= min)
+            && aged(self.newer_than, true)
+            && aged(self.older_than, false)
+    }
+
+    /// The files to back up, in file name order, walking the root without
+    /// descending into excluded directories. Unreadable entries are skipped.
+    pub fn walk(&self) -> Walk {
+        let filter = self.clone();
+        let mounts = Arc::new(AtomicU64::new(0));
+        let root_device = self.one_file_system.then(|| device(&self.root)).flatten();
+        let skipped_mounts = Arc::clone(&mounts);
+        let inner = WalkBuilder::new(&self.root)
+            .standard_filters(false)
+            .add_custom_ignore_filename(IGNORE_FILE)
+            .git_ignore(self.gitignore)
+            .require_git(false)
+            // Only where the device can't be compared below
+            .same_file_system(self.one_file_system && root_device.is_none())
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |e| {
+                let is_dir = e.file_type().is_some_and(|t| t.is_dir());
+                if e.depth() == 0 {
+                    return true;
+                }
+                if filter.is_excluded(e.path(), is_dir) || is_dir && filter.is_marked(e.path()) {
+                    return false;
+                }
+                if is_dir && root_device.is_some() && device(e.path()) != root_device {
+                    skipped_mounts.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                true
+            })
+            .build();
+        Walk {
+            inner,
+            filter: self.clone(),
+            skipped_files: 0,
+            mounts,
+        }
+    }
+}
+
+/// Items left out by a target's size, age and filesystem limits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Skipped {
+    /// Files outside the size or age limits.
+    pub files: u64,
+    /// Directories on other filesystems.
+    pub mounts: u64,
+}
+
+/// The files selected by a [`FileFilter`], counting what its limits skip.
+pub struct Walk {
+    inner: ignore::Walk,
+    filter: FileFilter,
+    skipped_files: u64,
+    mounts: Arc<AtomicU64>,
+}
+
+impl Walk {
+    /// What was skipped so far.
+    pub fn skipped(&self) -> Skipped {
+        Skipped {
+            files: self.skipped_files,
+            mounts: self.mounts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = DirEntry;
+
+    fn next(&mut self) -> Option<DirEntry> {
+        loop {
+            let Ok(entry) = self.inner.next()? else {
+                continue;
+            };
+            if !(entry.file_type().is_some_and(|t| t.is_file())
+                && self.filter.is_included(entry.path()))
+            {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if self
+                .filter
+                .is_within_limits(meta.len(), meta.modified().ok())
+            {
+                return Some(entry);
+            }
+            self.skipped_files += 1;
+        }
+    }
+}
+
+/// The files of all of a target's sources, each with its path in the
+/// snapshot.
+pub struct TargetWalk<'a> {
+    walks: Vec<(&'a Path, String, Walk)>,
+    current: usize,
+}
+
+impl<'a> TargetWalk<'a> {
+    pub fn new(target: &'a BackupTarget) -> Result<Self> {
+        let walks = target
+            .sources()
+            .into_iter()
+            .map(|(source, prefix)| {
+                Ok((
+                    source,
+                    prefix,
+                    FileFilter::for_source(target, source)?.walk(),
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { walks, current: 0 })
+    }
+
+    /// What was skipped so far, over all sources.
+    pub fn skipped(&self) -> Skipped {
+        self.walks.iter().map(|(_, _, walk)| walk.skipped()).fold(
+            Skipped::default(),
+            |total, skipped| Skipped {
+                files: total.files + skipped.files,
+                mounts: total.mounts + skipped.mounts,
+            },
+        )
+    }
+}
+
+impl Iterator for TargetWalk<'_> {
+    type Item = (DirEntry, String);
+
+    fn next(&mut self) -> Option<(DirEntry, String)> {
+        while let Some((source, prefix, walk)) = self.walks.get_mut(self.current) {
+            let Some(entry) = walk.next() else {
+                self.current += 1;
+                continue;
+            };
+            // Normalize path separators for cross-platform consistency
+            let relative = entry
+                .path()
+                .strip_prefix(source)
+                .unwrap_or(entry.path())
+                .to_string_lossy()
+                .replace('\\', "/");
+            let relative = if prefix.is_empty() {
+                relative
+            } else {
+                format!("{prefix}/{relative}")
+            };
+            return Some((entry, relative));
+        }
+        None
+    }
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::symlink_metadata(path).ok().map(|m| m.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Parses a size such as `4GiB`, `500M`, `10 k` or `1024`. Units are
+/// binary whichever spelling is used: `1K`, `1KB` and `1KiB` are 1024 bytes.
+pub fn parse_size(input: &str) -> anyhow::Result<u64> {
+    let invalid = || anyhow::anyhow!("invalid size '{input}' (expected e.g. 500M, 4GiB, 1024)");
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (digits, unit) = input.split_at(split);
+    let n: u64 = digits.parse().map_err(|_| invalid())?;
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => return Err(invalid()),
+    };
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| anyhow::anyhow!("size '{input}' is out of range"))
+}
+
+/// The modification time `age` (such as `90d`) before `now`, for
+/// `newer_than` and `older_than`.
+pub fn age_cutoff(now: DateTime<Utc>, age: &str) -> anyhow::Result<SystemTime> {
+    let cutoff = retention::subtract(now, age)?;
+    let secs = Duration::from_secs(cutoff.timestamp().unsigned_abs());
+    let whole = if cutoff.timestamp() >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(secs)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(secs)
+    };
+    whole
+        .and_then(|t| t.checked_add(Duration::from_nanos(cutoff.timestamp_subsec_nanos().into())))
+        .ok_or_else(|| anyhow::anyhow!("duration '{age}' is out of range"))
+}
+
+fn is_cache_dir(dir: &Path) -> bool {
+    let mut header = [0; CACHEDIR_SIGNATURE.len()];
+    std::fs::File::open(dir.join("CACHEDIR.TAG"))
+        .and_then(|mut f| f.read_exact(&mut header))
+        .is_ok_and(|()| header == CACHEDIR_SIGNATURE)
+}
+
+/// Checks that every pattern parses.
+pub fn validate_patterns(patterns: &[String]) -> std::result::Result<(), String> {
+    build(Path::new(""), patterns)
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+fn build(root: &Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| anyhow::anyhow!("invalid pattern '{pattern}': {e}"))?;
+    }
+    Ok(builder
+        .build()
+        .map_err(|e| anyhow::anyhow!("invalid patterns: {e}"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selected(name: &str, include: &[&str], exclude: &[&str]) -> Vec<String> {
+        let root = std::env::temp_dir().join(format!("but-next-test-filter-{name}"));
+        let _ = std::fs::remove_dir_all(&root);
+        for path in [
+            "a.log",
+            "keep.log",
+            "notes.md",
+            "docs/x.tmp",
+            "docs/guide.md",
+            "docs/deep/y.tmp",
+            "src/build/main.o",
+            "src/build/main.c",
+            "build/out.o",
+            "node_modules/pkg/index.js",
+        ] {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"x").unwrap();
+        }
+
+        let strings = |p: &[&str]| p.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let filter = FileFilter::new(&root, &strings(include), &strings(exclude)).unwrap();
+        let mut files: Vec<String> = filter
+            .walk()
+            .map(|e| {
+                let rel = e.path().strip_prefix(&root).unwrap();
+                rel.to_string_lossy().replace('\\', "/")
+            })
+            .collect();
+        files.sort();
+        std::fs::remove_dir_all(&root).unwrap();
+        files
+    }
+
+    #[test]
+    fn gitignore_patterns_select_files() {
+        let files = selected(
+            "gitignore",
+            &[],
+            &[
+                "*.log",
+                "!keep.log",
+                "docs/*.tmp",
+                "**/build/*.o",
+                "node_modules/",
+            ],
+        );
+        assert_eq!(
+            files,
+            [
+                "docs/deep/y.tmp",
+                "docs/guide.md",
+                "keep.log",
+                "notes.md",
+                "src/build/main.c",
+            ]
+        );
+
+        // Anchored to the source root
+        let files = selected("anchored", &[], &["/build", "[dn]*"]);
+        assert_eq!(
+            files,
+            ["a.log", "keep.log", "src/build/main.c", "src/build/main.o"]
+        );
+    }
+
+    #[test]
+    fn include_narrows_before_exclude() {
+        let files = selected("include", &["*.md", "src/"], &["*.o"]);
+        assert_eq!(files, ["docs/guide.md", "notes.md", "src/build/main.c"]);
+
+        assert!(validate_patterns(&["**/*.rs".to_string()]).is_ok());
+        assert!(validate_patterns(&["[z-a].txt".to_string()]).is_err());
+    }
+
+    #[test]
+    fn ignore_files_and_markers_exclude_directories() {
+        let root = std::env::temp_dir().join("but-next-test-filter-tree");
+        let _ = std::fs::remove_dir_all(&root);
+        let files: &[(&str, &[u8])] = &[
+            ("keep.txt", b"x"),
+            ("app/.butignore", b"*.log\n!audit.log\n"),
+            ("app/run.log", b"x"),
+            ("app/audit.log", b"x"),
+            ("app/sub/deep.log", b"x"),
+            ("other.log", b"x"),
+            ("web/.gitignore", b"dist/\n"),
+            ("web/dist/bundle.js", b"x"),
+            ("web/index.html", b"x"),
+            ("cache/CACHEDIR.TAG", CACHEDIR_SIGNATURE),
+            ("cache/blob", b"x"),
+            ("fake/CACHEDIR.TAG", b"not a cache"),
+            ("fake/data", b"x"),
+            ("vm/.nobackup", b""),
+            ("vm/disk.img", b"x"),
+        ];
+        for (path, content) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let target = BackupTarget {
+            from: vec![root.clone()],
+            exclude: vec!["audit.log".to_string()],
+            gitignore: true,
+            exclude_caches: true,
+            exclude_if_present: vec![".nobackup".to_string()],
+            ..BackupTarget::default()
+        };
+        let walked: Vec<String> = FileFilter::for_source(&target, &root)
+            .unwrap()
+            .walk()
+            .map(|e| {
+                let rel = e.path().strip_prefix(&root).unwrap();
+                rel.to_string_lossy().replace('\\', "/")
+            })
+            .collect();
+        assert_eq!(
+            walked,
+            [
+                "app/.butignore",
+                "fake/CACHEDIR.TAG",
+                "fake/data",
+                "keep.txt",
+                "other.log",
+                "web/.gitignore",
+                "web/index.html",
+            ]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn size_and_age_limits_skip_files() {
+        let root = std::env::temp_dir().join("but-next-test-filter-limits");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let day = std::time::Duration::from_secs(86_400);
+        for (name, size, age_days) in [
+            ("empty", 0, 1),
+            ("small", 10, 1),
+            ("large", 5000, 1),
+            ("old", 10, 400),
+            ("fresh", 10, 0),
+        ] {
+            let path = root.join(name);
+            std::fs::write(&path, vec![b'x'; size]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(SystemTime::now() - day * age_days)
+                .unwrap();
+        }
+
+        let target = BackupTarget {
+            from: vec![root.clone()],
+            min_file_size: Some("1".to_string()),
+            max_file_size: Some("4K".to_string()),
+            newer_than: Some("1y".to_string()),
+            older_than: Some("1h".to_string()),
+            ..BackupTarget::default()
+        };
+        let mut walk = FileFilter::for_source(&target, &root).unwrap().walk();
+        let names: Vec<String> = walk
+            .by_ref()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["small"]);
+        assert_eq!(
+            walk.skipped(),
+            Skipped {
+                files: 4,
+                mounts: 0
+            }
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn sizes_parse_with_binary_units() {
+        assert_eq!(parse_size("1024").unwrap(), 1024);
+        assert_eq!(parse_size("500M").unwrap(), 500 << 20);
+        assert_eq!(parse_size("4GiB").unwrap(), 4 << 30);
+        assert_eq!(parse_size("10 kb").unwrap(), 10 << 10);
+        assert!(parse_size("4 parsecs").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    fn age_cutoffs_are_checked() {
+        let now = Utc::now();
+        assert_eq!(
+            age_cutoff(now, "1d").unwrap(),
+            SystemTime::from(now - chrono::Duration::days(1))
+        );
+        assert!(age_cutoff(now, "100000000d").is_err());
+        assert!(age_cutoff(now, "1x").is_err());
+    }
+}
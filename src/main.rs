@@ -45,14 +45,25 @@
 //! | Progress display     | ✗            | ✓ (indicatif)         |
 //! | Tests                | ✗            | ✓                     |
 
+mod backend;
 mod backup;
+mod check;
 mod compress;
 mod config;
+mod control;
+mod copy;
 mod crypto;
+mod daemon;
 mod error;
+mod filter;
 mod hasher;
 mod manifest;
+mod parity;
 mod restore;
+mod retention;
+mod server;
+mod service;
+mod watch;
 
 use clap::{Parser, Subcommand};
 use std::path::{Path, PathBuf};
@@ -100,16 +111,28 @@ enum Command {
         /// Encryption password (or set BUT_NEXT_PASSWORD env var)
         #[arg(short, long)]
         password: Option<String>,
+
+        /// Tag the new snapshot(s) (repeatable or comma-separated)
+        #[arg(long = "tag", value_delimiter = ',')]
+        tags: Vec<String>,
+
+        /// Describe why the snapshot was taken
+        #[arg(short, long)]
+        message: Option<String>,
     },
 
     /// Restore files from a snapshot
     Restore {
-        /// Snapshot ID or prefix to restore from
+        /// Snapshot to restore from (ID prefix, latest, <target>~N, @DATE or tag:NAME)
         snapshot: String,
 
         /// Target directory to restore into
-        #[arg(short, long)]
-        output: PathBuf,
+        #[arg(short, long, required_unless_present = "original")]
+        output: Option<PathBuf>,
+
+        /// Restore files to the paths they were backed up from
+        #[arg(long, conflicts_with = "output")]
+        original: bool,
 
         /// Overwrite existing files
         #[arg(short, long)]
@@ -128,18 +151,41 @@ enum Command {
         password: Option<String>,
     },
 
-    /// List all snapshots (optionally filtered by target)
+    /// List all snapshots (optionally filtered by target, tag or host)
     List {
         /// Filter snapshots by target name
         #[arg(short, long)]
         target: Option<String>,
+
+        /// Only snapshots with any of these tags (repeatable or comma-separated)
+        #[arg(long = "tag", value_delimiter = ',')]
+        tags: Vec<String>,
+
+        /// Only snapshots created on this host
+        #[arg(long)]
+        host: Option<String>,
+    },
+
+    /// List the files in a snapshot
+    Ls {
+        /// Snapshot (ID prefix, latest, <target>~N, @DATE or tag:NAME)
+        snapshot: String,
+
+        /// Only list paths starting with these prefixes
+        prefixes: Vec<String>,
+    },
+
+    /// List the files a backup of a target would include
+    LsFiles {
+        /// Target name
+        target: String,
     },
 
     /// Show differences between two snapshots
     Diff {
-        /// Older snapshot ID (or prefix)
+        /// Older snapshot (ID prefix, latest, <target>~N, @DATE or tag:NAME)
         older: String,
-        /// Newer snapshot ID (or prefix)
+        /// Newer snapshot
         newer: String,
 
         /// Show full file listing
@@ -147,30 +193,235 @@ enum Command {
         detail: bool,
     },
 
-    /// Remove old snapshots, keeping the most recent N per target
+    /// Remove old snapshots according to retention rules
+    ///
+    /// Without --keep-* options the rules from the configuration are used.
     Prune {
-        /// Target to prune
-        target: String,
+        /// Target to prune (default: every target with snapshots)
+        target: Option<String>,
+
+        /// Keep the N most recent snapshots
+        #[arg(short = 'k', long, visible_alias = "keep")]
+        keep_last: Option<usize>,
+
+        /// Keep the newest snapshot of each of the last N hours
+        #[arg(long)]
+        keep_hourly: Option<usize>,
+
+        /// Keep the newest snapshot of each of the last N days
+        #[arg(long)]
+        keep_daily: Option<usize>,
+
+        /// Keep the newest snapshot of each of the last N weeks
+        #[arg(long)]
+        keep_weekly: Option<usize>,
+
+        /// Keep the newest snapshot of each of the last N months
+        #[arg(long)]
+        keep_monthly: Option<usize>,
+
+        /// Keep the newest snapshot of each of the last N years
+        #[arg(long)]
+        keep_yearly: Option<usize>,
+
+        /// Keep every snapshot within this duration of the newest (e.g. 7d, 2w, 1y6m)
+        #[arg(long)]
+        keep_within: Option<String>,
+
+        /// Remove exactly these snapshots instead of applying retention rules
+        /// (ID prefix, latest, <target>~N, @DATE or tag:NAME; repeatable)
+        #[arg(
+            short,
+            long = "snapshot",
+            conflicts_with_all = [
+                "target", "keep_last", "keep_hourly", "keep_daily",
+                "keep_weekly", "keep_monthly", "keep_yearly", "keep_within",
+            ],
+        )]
+        snapshots: Vec<String>,
+
+        /// Show what would be removed and why, without deleting anything
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
 
-        /// Number of most recent snapshots to keep
-        #[arg(short, long, default_value_t = 5)]
-        keep: usize,
+    /// Show or change a snapshot's tags and description
+    Tag {
+        /// Snapshot (ID prefix, latest, <target>~N, @DATE or tag:NAME)
+        snapshot: String,
+
+        /// Tags to add (repeatable or comma-separated)
+        #[arg(short, long, value_delimiter = ',')]
+        add: Vec<String>,
+
+        /// Tags to remove (repeatable or comma-separated)
+        #[arg(short, long, value_delimiter = ',')]
+        remove: Vec<String>,
+
+        /// Replace the description (an empty string clears it)
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// Protect a snapshot from prune and deletion
+    Pin {
+        /// Snapshot (ID prefix, latest, <target>~N, @DATE or tag:NAME)
+        snapshot: String,
+
+        /// Let the pin lapse at a date (YYYY-MM-DD) or after a duration (e.g. 90d, 1y)
+        #[arg(long)]
+        until: Option<String>,
+    },
+
+    /// Remove the pin from a snapshot
+    Unpin {
+        /// Snapshot (ID prefix, latest, <target>~N, @DATE or tag:NAME)
+        snapshot: String,
+    },
+
+    /// Copy snapshots to another repository, transferring only missing blobs
+    Copy {
+        /// Destination repository path or URL
+        #[arg(long)]
+        to: String,
+
+        /// Snapshots to copy, as IDs or selectors (default: all)
+        snapshots: Vec<String>,
+
+        /// Only copy snapshots of this target
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// Source password (or set BUT_NEXT_PASSWORD env var)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Destination password if it differs; blobs are re-encrypted
+        /// (or set BUT_NEXT_TO_PASSWORD env var)
+        #[arg(long)]
+        to_password: Option<String>,
     },
 
     /// Verify integrity of a snapshot's blobs
     Verify {
-        /// Snapshot ID or prefix to verify
+        /// Snapshot to verify (ID prefix, latest, <target>~N, @DATE or tag:NAME)
         snapshot: String,
     },
 
-    /// Watch for changes and backup on interval
+    /// Audit the whole repository: manifests, blob contents and references
+    Check {
+        /// Decryption password (needed to verify encrypted blobs)
+        #[arg(short, long)]
+        password: Option<String>,
+    },
+
+    /// Quarantine corrupted data and mark damaged files in affected snapshots
+    Repair {
+        /// Decryption password (needed to verify encrypted blobs)
+        #[arg(short, long)]
+        password: Option<String>,
+
+        /// Re-store lost blobs from unchanged files in the source directories
+        #[arg(long)]
+        from_source: bool,
+    },
+
+    /// Serve a repository to remote clients over HTTP(S)
+    Serve {
+        /// Repository path or URL to serve (default: the configured repository)
+        repository: Option<String>,
+
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8000")]
+        listen: String,
+
+        /// Client credentials file
+        #[arg(long)]
+        clients: PathBuf,
+
+        /// Only allow adding data: refuse deletes and overwrites
+        #[arg(long)]
+        append_only: bool,
+
+        /// Give each client its own repository below <repository>/<client>/
+        #[arg(long)]
+        private_repos: bool,
+
+        /// Largest object a client may upload, e.g. 512M or 4G (held in memory)
+        #[arg(long, default_value = "1G", value_parser = parse_size_arg)]
+        max_object_size: u64,
+
+        /// PEM certificate for HTTPS
+        #[arg(long, requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+
+        /// PEM private key for HTTPS
+        #[arg(long, requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+
+        /// Add a client to the credentials file (with --append-only to restrict it) and exit
+        #[arg(long, value_name = "NAME")]
+        add_client: Option<String>,
+
+        /// Password for --add-client
+        #[arg(short, long)]
+        password: Option<String>,
+    },
+
+    /// Watch sources and back up targets as they change
     Watch {
         /// Encryption password
         #[arg(short, long)]
         password: Option<String>,
+
+        /// Rescan every `interval` seconds instead of using change notifications
+        #[arg(long)]
+        poll: bool,
+
+        /// Control socket (default: $XDG_RUNTIME_DIR/but-next/control.sock)
+        #[arg(long)]
+        socket: Option<PathBuf>,
+    },
+
+    /// Run backups, pruning and checks on their cron schedules
+    Daemon {
+        /// Encryption password
+        #[arg(short, long)]
+        password: Option<String>,
+    },
+
+    /// Query or steer a running `watch` through its control socket
+    Ctl {
+        /// Control socket of the watch (default: $XDG_RUNTIME_DIR/but-next/control.sock)
+        #[arg(long)]
+        socket: Option<PathBuf>,
+
+        #[command(subcommand)]
+        action: CtlAction,
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum CtlAction {
+    /// Show the current backup and the last result of each target
+    Status,
+
+    /// Back up now, without waiting for changes
+    Trigger {
+        /// Only this target
+        target: Option<String>,
+    },
+
+    /// Stop starting backups; changes are still recorded
+    Pause,
+
+    /// Resume backups, starting with what changed while paused
+    Resume,
+
+    /// Stop the watch after the file being stored
+    Shutdown,
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -183,12 +434,22 @@ fn main() {
 fn run(cli: Cli) -> error::Result<()> {
     match &cli.command {
         Command::Init { output } => cmd_init(output),
-        Command::Backup { target, password } => {
-            cmd_backup(&cli, target.as_deref(), password.as_deref())
-        }
+        Command::Backup {
+            target,
+            password,
+            tags,
+            message,
+        } => cmd_backup(
+            &cli,
+            target.as_deref(),
+            password.as_deref(),
+            tags,
+            message.as_deref(),
+        ),
         Command::Restore {
             snapshot,
             output,
+            original: _,
             force,
             verify,
             filter,
@@ -196,21 +457,110 @@ fn run(cli: Cli) -> error::Result<()> {
         } => cmd_restore(
             &cli,
             snapshot,
-            output,
+            output.as_deref(),
             *force,
             *verify,
             filter.clone(),
             password.as_deref(),
         ),
-        Command::List { target } => cmd_list(&cli, target.as_deref()),
+        Command::List { target, tags, host } => {
+            cmd_list(&cli, target.as_deref(), tags, host.as_deref())
+        }
+        Command::Ls { snapshot, prefixes } => cmd_ls(&cli, snapshot, prefixes),
+        Command::LsFiles { target } => cmd_ls_files(&cli, target),
         Command::Diff {
             older,
             newer,
             detail,
         } => cmd_diff(&cli, older, newer, *detail),
-        Command::Prune { target, keep } => cmd_prune(&cli, target, *keep),
+        Command::Prune {
+            target,
+            keep_last,
+            keep_hourly,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            keep_yearly,
+            keep_within,
+            snapshots,
+            dry_run,
+        } => cmd_prune(
+            &cli,
+            target.as_deref(),
+            snapshots,
+            *dry_run,
+            retention::RetentionPolicy {
+                keep_last: *keep_last,
+                keep_hourly: *keep_hourly,
+                keep_daily: *keep_daily,
+                keep_weekly: *keep_weekly,
+                keep_monthly: *keep_monthly,
+                keep_yearly: *keep_yearly,
+                keep_within: keep_within.clone(),
+            },
+        ),
+        Command::Tag {
+            snapshot,
+            add,
+            remove,
+            message,
+        } => cmd_tag(&cli, snapshot, add, remove, message.as_deref()),
+        Command::Pin { snapshot, until } => cmd_pin(&cli, snapshot, true, until.as_deref()),
+        Command::Unpin { snapshot } => cmd_pin(&cli, snapshot, false, None),
+        Command::Copy {
+            to,
+            snapshots,
+            target,
+            password,
+            to_password,
+        } => cmd_copy(
+            &cli,
+            to,
+            snapshots,
+            target.as_deref(),
+            password.as_deref(),
+            to_password.as_deref(),
+        ),
         Command::Verify { snapshot } => cmd_verify(&cli, snapshot),
-        Command::Watch { password } => cmd_watch(&cli, password.as_deref()),
+        Command::Check { password } => cmd_check(&cli, password.as_deref()),
+        Command::Repair {
+            password,
+            from_source,
+        } => cmd_repair(&cli, password.as_deref(), *from_source),
+        Command::Serve {
+            repository,
+            listen,
+            clients,
+            append_only,
+            private_repos,
+            tls_cert,
+            tls_key,
+            max_object_size,
+            add_client,
+            password,
+        } => match add_client {
+            Some(name) => cmd_add_client(clients, name, password.as_deref(), *append_only),
+            None => cmd_serve(
+                &cli,
+                repository.as_deref(),
+                server::ServeOptions {
+                    listen: listen.clone(),
+                    append_only: *append_only,
+                    private_repos: *private_repos,
+                    tls: tls_cert.clone().zip(tls_key.clone()),
+                    max_object_size: *max_object_size,
+                    verbose: cli.verbose,
+                },
+                clients,
+            ),
+        },
+        Command::Watch {
+            password,
+            poll,
+            socket,
+        } => cmd_watch(&cli, password.as_deref(), *poll, socket.as_deref()),
+        Command::Daemon { password } => cmd_daemon(&cli, password.as_deref()),
+        Command::Ctl { socket, action } => cmd_ctl(socket.as_deref(), action),
     }
 }
 
@@ -228,19 +578,41 @@ fn cmd_init(output: &Path) -> error::Result<()> {
     Ok(())
 }
 
-fn cmd_backup(cli: &Cli, target: Option<&str>, password: Option<&str>) -> error::Result<()> {
+fn cmd_backup(
+    cli: &Cli,
+    target: Option<&str>,
+    password: Option<&str>,
+    tags: &[String],
+    message: Option<&str>,
+) -> error::Result<()> {
     let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
     let password = password
         .map(String::from)
         .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
+    for tag in tags {
+        manifest::validate_tag(tag)?;
+    }
+    let opts = backup::BackupOptions {
+        password: password.as_deref(),
+        tags,
+        message,
+        skip_unchanged: cfg.settings.skip_unchanged.unwrap_or(false),
+        verbose: cli.verbose,
+        ..Default::default()
+    };
 
     print_header("Backup");
+    eprintln!("  Repository: {}", repo.location());
 
     if let Some(target_name) = target {
         let target_config = cfg
-            .backup
-            .get(target_name)
+            .configured(target_name)
             .ok_or_else(|| anyhow::anyhow!("target '{target_name}' not found in configuration"))?;
+        if target_config.is_dynamic() {
+            backup::backup_targets(&cfg, repo.as_ref(), &[target_name], &opts)?;
+            return Ok(());
+        }
 
         eprintln!(
             "\n{} Backing up: {}",
@@ -248,16 +620,28 @@ fn cmd_backup(cli: &Cli, target: Option<&str>, password: Option<&str>) -> error:
             colored::Colorize::bold(target_name),
         );
 
-        let snapshot = backup::backup_target(
+        let parent = manifest::latest_snapshots(repo.as_ref())?.remove(target_name);
+        let result = backup::backup_target(
             &cfg.settings,
+            repo.as_ref(),
             target_name,
             target_config,
-            password.as_deref(),
-            cli.verbose,
-        )?;
+            parent.as_ref(),
+            &opts,
+        );
+        let snapshot = match result {
+            Err(error::ButError::Backup(error::BackupError::NothingChanged(parent))) => {
+                backup::print_nothing_changed(&parent);
+                return Ok(());
+            }
+            result => result?,
+        };
         backup::print_snapshot_summary(&snapshot);
+        if cfg.settings.auto_prune {
+            backup::auto_prune(&cfg, repo.as_ref(), target_name);
+        }
     } else {
-        backup::backup_all(&cfg, password.as_deref(), cli.verbose)?;
+        backup::backup_all(&cfg, repo.as_ref(), &opts)?;
     }
 
     Ok(())
@@ -266,33 +650,43 @@ fn cmd_backup(cli: &Cli, target: Option<&str>, password: Option<&str>) -> error:
 fn cmd_restore(
     cli: &Cli,
     snapshot_id: &str,
-    output: &Path,
+    output: Option<&Path>,
     force: bool,
     verify: bool,
     filter: Option<Vec<String>>,
     password: Option<&str>,
 ) -> error::Result<()> {
     let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
     let password = password
         .map(String::from)
         .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
 
     print_header("Restore");
 
-    let snapshot = manifest::find_snapshot(&cfg.settings.repo_path, snapshot_id)?
-        .ok_or_else(|| anyhow::anyhow!("snapshot '{snapshot_id}' not found"))?;
+    let snapshot = manifest::resolve_snapshot(repo.as_ref(), snapshot_id)?;
 
     eprintln!(
         "  Snapshot:  {} ({})",
         colored::Colorize::bold(snapshot.id.as_str()),
-        snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
+        local_time(&snapshot.created_at).format("%Y-%m-%d %H:%M:%S"),
     );
-    eprintln!("  Target:    {}", output.display());
+    match output {
+        Some(output) => eprintln!("  Target:    {}", output.display()),
+        None if snapshot.sources.is_empty() => {
+            eprintln!("  Target:    {} (original)", snapshot.source_path.display())
+        }
+        None => {
+            for source in &snapshot.sources {
+                eprintln!("  Target:    {} (original)", source.display());
+            }
+        }
+    }
     eprintln!("  Files:     {}", snapshot.stats.total_files);
     eprintln!();
 
     let opts = restore::RestoreOptions {
-        target_dir: output.to_path_buf(),
+        target_dir: output.map(Path::to_path_buf),
         password: password.as_deref(),
         force,
         verify,
@@ -300,7 +694,7 @@ fn cmd_restore(
         verbose: cli.verbose,
     };
 
-    let stats = restore::restore_snapshot(&cfg.settings, &snapshot, &opts)?;
+    let stats = restore::restore_snapshot(repo.as_ref(), &snapshot, &opts)?;
 
     eprintln!();
     eprintln!(
@@ -309,42 +703,81 @@ fn cmd_restore(
         stats.files_restored,
         backup::format_size(stats.bytes_restored),
     );
+    if stats.files_damaged > 0 {
+        eprintln!(
+            "  {} Skipped {} damaged file(s)",
+            colored::Colorize::yellow("!"),
+            stats.files_damaged,
+        );
+    }
 
     Ok(())
 }
 
-fn cmd_list(cli: &Cli, target: Option<&str>) -> error::Result<()> {
+fn cmd_list(
+    cli: &Cli,
+    target: Option<&str>,
+    tags: &[String],
+    host: Option<&str>,
+) -> error::Result<()> {
     let cfg = load_config(cli)?;
-
-    let snapshots = if let Some(target_name) = target {
-        manifest::list_snapshots_for_target(&cfg.settings.repo_path, target_name)?
-    } else {
-        manifest::list_snapshots(&cfg.settings.repo_path)?
-    };
+    let repo = backend::open(&cfg.settings)?;
+
+    // Prefixes are shortened against every snapshot so that they stay
+    // unambiguous when passed back to other commands
+    let mut snapshots = manifest::list_snapshots(repo.as_ref())?;
+    let short_ids = manifest::short_ids(&snapshots);
+    snapshots.retain(|s| {
+        target.map_or(true, |t| cfg.selects(t, &s.target_name))
+            && (tags.is_empty() || tags.iter().any(|t| s.has_tag(t)))
+            && host.map_or(true, |h| s.hostname == h)
+    });
 
     if snapshots.is_empty() {
         eprintln!("No snapshots found.");
         return Ok(());
     }
 
+    let id_width = snapshots
+        .iter()
+        .map(|s| short_ids[&s.id].len())
+        .max()
+        .unwrap_or(manifest::SHORT_ID_LEN);
     eprintln!(
-        "{:>4}  {:30}  {:12}  {:>8}  {:>10}  {:>10}",
-        "#", "Snapshot ID", "Target", "Files", "Size", "Stored"
+        "{:>4}  {:id_width$}  {:16}  {:12}  {:12}  {:>8}  {:>10}  {:>10}",
+        "#", "ID", "Created", "Target", "Host", "Files", "Size", "Stored"
     );
-    eprintln!("{}", "─".repeat(88));
+    eprintln!("{}", "─".repeat(90 + id_width));
 
     for (i, snap) in snapshots.iter().enumerate() {
         let enc = if snap.encrypted { "🔒" } else { "  " };
+        let pin = if snap.is_pinned() { "📌" } else { "" };
+        let tags = if snap.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", snap.tags.join(", "))
+        };
         eprintln!(
-            "{:>4}  {:30}  {:12}  {:>8}  {:>10}  {:>10} {}",
+            "{:>4}  {:id_width$}  {:16}  {:12}  {:12}  {:>8}  {:>10}  {:>10} {}{}{}",
             i + 1,
-            snap.id,
+            short_ids[&snap.id],
+            local_time(&snap.created_at).format("%Y-%m-%d %H:%M"),
             snap.target_name,
+            if snap.hostname.is_empty() {
+                "-"
+            } else {
+                snap.hostname.as_str()
+            },
             snap.stats.total_files,
             backup::format_size(snap.stats.total_size),
             backup::format_size(snap.stats.stored_size),
             enc,
+            pin,
+            colored::Colorize::cyan(tags.as_str()),
         );
+        if let Some(message) = &snap.message {
+            eprintln!("{:6}{}", "", colored::Colorize::dimmed(message.as_str()));
+        }
     }
 
     eprintln!();
@@ -353,25 +786,137 @@ fn cmd_list(cli: &Cli, target: Option<&str>) -> error::Result<()> {
     Ok(())
 }
 
+fn cmd_ls(cli: &Cli, snapshot_id: &str, prefixes: &[String]) -> error::Result<()> {
+    let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
+
+    let snapshot = manifest::resolve_snapshot(repo.as_ref(), snapshot_id)?;
+    eprintln!(
+        "  Snapshot: {} ({}, {})",
+        snapshot.id,
+        snapshot.target_name,
+        local_time(&snapshot.created_at).format("%Y-%m-%d %H:%M:%S"),
+    );
+    eprintln!();
+
+    let mut count = 0u64;
+    let mut size = 0u64;
+    for (path, entry) in &snapshot.files {
+        if !prefixes.is_empty() && !prefixes.iter().any(|p| path.starts_with(p.as_str())) {
+            continue;
+        }
+        let modified = chrono::DateTime::from_timestamp(entry.modified as i64, 0)
+            .map(|t| {
+                t.with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        eprintln!(
+            "  {:>10}  {:16}  {}{}",
+            backup::format_size(entry.size),
+            modified,
+            path,
+            if entry.damaged {
+                colored::Colorize::red(" (damaged)").to_string()
+            } else {
+                String::new()
+            },
+        );
+        count += 1;
+        size += entry.size;
+    }
+
+    eprintln!();
+    eprintln!("  {} file(s), {}", count, backup::format_size(size));
+
+    Ok(())
+}
+
+fn cmd_ls_files(cli: &Cli, target_name: &str) -> error::Result<()> {
+    let cfg = load_config(cli)?;
+    if cfg.configured(target_name).is_none() {
+        return Err(anyhow::anyhow!("target '{target_name}' not found in configuration").into());
+    }
+    let targets = cfg.select_targets(target_name);
+    if targets.is_empty() {
+        eprintln!(
+            "  {} No directory matches {}",
+            colored::Colorize::yellow("!"),
+            cfg.configured(target_name)
+                .map(|t| t.display_sources())
+                .unwrap_or_default(),
+        );
+    }
+    for (i, (name, target)) in targets.iter().enumerate() {
+        if i > 0 {
+            eprintln!();
+        }
+        ls_target_files(name, target)?;
+    }
+    Ok(())
+}
+
+fn ls_target_files(target_name: &str, target: &config::BackupTarget) -> error::Result<()> {
+    eprintln!("  Target: {} ({})", target_name, target.display_sources());
+    eprintln!();
+
+    let mut count = 0u64;
+    let mut size = 0u64;
+    let mut walk = filter::TargetWalk::new(target)?;
+    for (entry, path) in walk.by_ref() {
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let modified = metadata
+            .modified()
+            .map(|t| {
+                chrono::DateTime::<chrono::Local>::from(t)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        eprintln!(
+            "  {:>10}  {:16}  {}",
+            backup::format_size(metadata.len()),
+            modified,
+            path,
+        );
+        count += 1;
+        size += metadata.len();
+    }
+    let skipped = walk.skipped();
+
+    eprintln!();
+    eprintln!("  {} file(s), {}", count, backup::format_size(size));
+    if skipped.files > 0 || skipped.mounts > 0 {
+        eprintln!(
+            "  Skipped {} file(s) by size or age, {} mount point(s)",
+            skipped.files, skipped.mounts,
+        );
+    }
+
+    Ok(())
+}
+
 fn cmd_diff(cli: &Cli, older_id: &str, newer_id: &str, detail: bool) -> error::Result<()> {
     let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
 
-    let older = manifest::find_snapshot(&cfg.settings.repo_path, older_id)?
-        .ok_or_else(|| anyhow::anyhow!("snapshot '{older_id}' not found"))?;
+    let older = manifest::resolve_snapshot(repo.as_ref(), older_id)?;
 
-    let newer = manifest::find_snapshot(&cfg.settings.repo_path, newer_id)?
-        .ok_or_else(|| anyhow::anyhow!("snapshot '{newer_id}' not found"))?;
+    let newer = manifest::resolve_snapshot(repo.as_ref(), newer_id)?;
 
     eprintln!("  Comparing:");
     eprintln!(
         "    older: {} ({})",
         older.id,
-        older.created_at.format("%Y-%m-%d %H:%M:%S")
+        local_time(&older.created_at).format("%Y-%m-%d %H:%M:%S")
     );
     eprintln!(
         "    newer: {} ({})",
         newer.id,
-        newer.created_at.format("%Y-%m-%d %H:%M:%S")
+        local_time(&newer.created_at).format("%Y-%m-%d %H:%M:%S")
     );
     eprintln!();
 
@@ -386,32 +931,308 @@ fn cmd_diff(cli: &Cli, older_id: &str, newer_id: &str, detail: bool) -> error::R
     Ok(())
 }
 
-fn cmd_prune(cli: &Cli, target: &str, keep: usize) -> error::Result<()> {
+fn cmd_prune(
+    cli: &Cli,
+    target: Option<&str>,
+    selectors: &[String],
+    dry_run: bool,
+    overrides: retention::RetentionPolicy,
+) -> error::Result<()> {
     let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
+    if let Some(within) = &overrides.keep_within {
+        retention::parse_duration(within)?;
+    }
 
-    print_header("Prune");
-
-    let (deleted, freed) = backup::prune_snapshots(&cfg.settings.repo_path, target, keep)?;
+    print_header(if dry_run { "Prune (dry run)" } else { "Prune" });
 
-    if deleted == 0 {
-        eprintln!("  Nothing to prune (≤{keep} snapshots exist for '{target}').");
+    let plan = if selectors.is_empty() {
+        plan_retention(&cfg, repo.as_ref(), target, overrides)?
     } else {
+        let all = manifest::list_snapshots(repo.as_ref())?;
+        let mut selected = Vec::new();
+        for selector in selectors {
+            selected.push(manifest::select_snapshot(&all, selector)?.clone());
+        }
+        backup::plan_removal(repo.as_ref(), &selected)?
+    };
+
+    let removals = plan.removals().count();
+
+    if dry_run || cli.verbose || !selectors.is_empty() {
+        print_prune_plan(&plan);
+    }
+    if removals == 0 {
+        eprintln!("  Nothing to prune.");
+        return Ok(());
+    }
+
+    if dry_run {
+        let exclusive: u64 = plan.removals().map(|p| p.frees).sum();
         eprintln!(
-            "  {} Pruned {} snapshot(s), freed {}",
+            "  Would remove {} snapshot(s), freeing {}{}",
+            removals,
+            backup::format_size(plan.total_frees),
+            if plan.total_frees > exclusive {
+                format!(
+                    " ({} only once all of them are gone)",
+                    backup::format_size(plan.total_frees - exclusive)
+                )
+            } else {
+                String::new()
+            },
+        );
+        return Ok(());
+    }
+
+    let (deleted, freed) = backup::execute_prune(repo.as_ref(), &plan)?;
+    eprintln!(
+        "  {} Pruned {} snapshot(s), freed {}",
+        colored::Colorize::green("✓"),
+        deleted,
+        backup::format_size(freed),
+    );
+
+    Ok(())
+}
+
+/// Plans a prune from retention rules: `overrides` if it has any, otherwise
+/// each target's configured rules.
+fn plan_retention(
+    cfg: &config::Config,
+    repo: &dyn backend::Backend,
+    target: Option<&str>,
+    overrides: retention::RetentionPolicy,
+) -> error::Result<backup::PrunePlan> {
+    let mut targets: Vec<String> = manifest::list_snapshots(repo)?
+        .into_iter()
+        .map(|s| s.target_name)
+        .filter(|t| target.map_or(true, |name| cfg.selects(name, t)))
+        .collect();
+    targets.sort();
+    targets.dedup();
+    // Without snapshots, the target is still checked for retention rules
+    if let Some(name) = target.filter(|_| targets.is_empty()) {
+        targets.push(name.to_string());
+    }
+
+    let mut policies = Vec::new();
+    for name in targets {
+        // Options on the command line replace the configured rules entirely
+        let policy = if overrides.is_empty() {
+            cfg.retention_for(&name)
+        } else {
+            overrides.clone()
+        };
+        if policy.is_empty() && cfg.settings.max_snapshots == 0 {
+            eprintln!(
+                "  {} {name}: no retention rules configured, keeping everything",
+                colored::Colorize::yellow("!"),
+            );
+            continue;
+        }
+        policies.push((name, policy));
+    }
+
+    backup::plan_prune(repo, &policies, cfg.settings.max_snapshots)
+}
+
+fn print_prune_plan(plan: &backup::PrunePlan) {
+    for (target, planned) in &plan.targets {
+        eprintln!("  {}", colored::Colorize::bold(target.as_str()));
+        for p in planned {
+            let reasons = p
+                .reasons
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ");
+            if p.keep {
+                eprintln!(
+                    "    {}  {:32}  {}  {}",
+                    colored::Colorize::green("keep  "),
+                    p.snapshot.id,
+                    local_time(&p.snapshot.created_at).format("%Y-%m-%d %H:%M"),
+                    colored::Colorize::dimmed(reasons.as_str()),
+                );
+            } else {
+                eprintln!(
+                    "    {}  {:32}  {}  {}  frees {}",
+                    colored::Colorize::red("remove"),
+                    p.snapshot.id,
+                    local_time(&p.snapshot.created_at).format("%Y-%m-%d %H:%M"),
+                    colored::Colorize::dimmed(reasons.as_str()),
+                    backup::format_size(p.frees),
+                );
+            }
+        }
+        eprintln!();
+    }
+}
+
+fn cmd_tag(
+    cli: &Cli,
+    snapshot_id: &str,
+    add: &[String],
+    remove: &[String],
+    message: Option<&str>,
+) -> error::Result<()> {
+    let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
+
+    let mut snapshot = manifest::resolve_snapshot(repo.as_ref(), snapshot_id)?;
+
+    for tag in add {
+        manifest::validate_tag(tag)?;
+    }
+    let changed = !add.is_empty() || !remove.is_empty() || message.is_some();
+    if changed {
+        snapshot.add_tags(add);
+        snapshot.remove_tags(remove);
+        if let Some(message) = message {
+            snapshot.message = Some(message.to_string()).filter(|m| !m.is_empty());
+        }
+        manifest::save_snapshot(repo.as_ref(), &snapshot)?;
+        eprintln!(
+            "  {} Updated {}",
             colored::Colorize::green("✓"),
-            deleted,
-            backup::format_size(freed),
+            snapshot.id
         );
     }
 
+    let or_dash = |s: &str| {
+        if s.is_empty() {
+            "-".to_string()
+        } else {
+            s.to_string()
+        }
+    };
+    eprintln!("  Snapshot: {}", snapshot.id);
+    eprintln!(
+        "  Created:  {}",
+        local_time(&snapshot.created_at).format("%Y-%m-%d %H:%M:%S")
+    );
+    eprintln!(
+        "  Host:     {} (user {})",
+        or_dash(&snapshot.hostname),
+        or_dash(&snapshot.username),
+    );
+    eprintln!("  Version:  {}", or_dash(&snapshot.version));
+    if !snapshot.command_line.is_empty() {
+        eprintln!("  Command:  {}", snapshot.command_line.join(" "));
+    }
+    eprintln!("  Tags:     {}", or_dash(&snapshot.tags.join(", ")));
+    eprintln!(
+        "  Message:  {}",
+        or_dash(snapshot.message.as_deref().unwrap_or(""))
+    );
+
+    Ok(())
+}
+
+fn cmd_pin(cli: &Cli, snapshot_id: &str, pin: bool, until: Option<&str>) -> error::Result<()> {
+    let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
+
+    let mut snapshot = manifest::resolve_snapshot(repo.as_ref(), snapshot_id)?;
+
+    snapshot.pinned = pin;
+    snapshot.pinned_until = match until {
+        Some(spec) => Some(retention::parse_expiry(spec, chrono::Local::now())?),
+        None => None,
+    };
+    manifest::save_snapshot(repo.as_ref(), &snapshot)?;
+
+    match (pin, snapshot.pinned_until) {
+        (true, Some(until)) => eprintln!(
+            "  {} Pinned {} until {}",
+            colored::Colorize::green("✓"),
+            snapshot.id,
+            local_time(&until).format("%Y-%m-%d %H:%M"),
+        ),
+        (true, None) => eprintln!("  {} Pinned {}", colored::Colorize::green("✓"), snapshot.id),
+        (false, _) => eprintln!(
+            "  {} Unpinned {}",
+            colored::Colorize::green("✓"),
+            snapshot.id
+        ),
+    }
+
+    Ok(())
+}
+
+fn cmd_copy(
+    cli: &Cli,
+    to: &str,
+    selectors: &[String],
+    target: Option<&str>,
+    password: Option<&str>,
+    to_password: Option<&str>,
+) -> error::Result<()> {
+    let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
+    let dest = backend::open_url(to, &cfg.settings)?;
+    let password = password
+        .map(String::from)
+        .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
+    let to_password = to_password
+        .map(String::from)
+        .or_else(|| std::env::var("BUT_NEXT_TO_PASSWORD").ok());
+
+    print_header("Copy");
+    eprintln!("  Repository:  {}", repo.location());
+    eprintln!("  Destination: {}", dest.location());
+
+    let mut snapshots = if selectors.is_empty() {
+        manifest::list_snapshots(repo.as_ref())?
+    } else {
+        let mut selected = Vec::new();
+        for selector in selectors {
+            let snapshot = manifest::resolve_snapshot(repo.as_ref(), selector)?;
+            selected.push(snapshot);
+        }
+        selected
+    };
+    if let Some(target) = target {
+        snapshots.retain(|s| cfg.selects(target, &s.target_name));
+    }
+    eprintln!();
+
+    let opts = copy::CopyOptions {
+        password: password.as_deref(),
+        dest_password: to_password.as_deref(),
+        parity: cfg.settings.parity,
+        verbose: cli.verbose,
+    };
+    let report = copy::copy_snapshots(repo.as_ref(), dest.as_ref(), &snapshots, &opts)?;
+
+    eprintln!(
+        "  {} Copied {} snapshot(s), {} already present",
+        colored::Colorize::green("✓"),
+        report.snapshots_copied,
+        report.snapshots_present,
+    );
+    eprintln!(
+        "  {} Transferred {} blob(s) ({}), {} already present{}",
+        colored::Colorize::green("✓"),
+        report.blobs_copied,
+        backup::format_size(report.bytes_copied),
+        report.blobs_present,
+        if report.blobs_reencrypted > 0 {
+            format!(", {} re-encrypted", report.blobs_reencrypted)
+        } else {
+            String::new()
+        },
+    );
+
     Ok(())
 }
 
 fn cmd_verify(cli: &Cli, snapshot_id: &str) -> error::Result<()> {
     let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
 
-    let snapshot = manifest::find_snapshot(&cfg.settings.repo_path, snapshot_id)?
-        .ok_or_else(|| anyhow::anyhow!("snapshot '{snapshot_id}' not found"))?;
+    let snapshot = manifest::resolve_snapshot(repo.as_ref(), snapshot_id)?;
 
     eprintln!(
         "  Verifying snapshot: {} ({} files)",
@@ -419,24 +1240,48 @@ fn cmd_verify(cli: &Cli, snapshot_id: &str) -> error::Result<()> {
     );
 
     let mut ok = 0u64;
+    let mut recoverable = 0u64;
     let mut missing = 0u64;
+    let mut corrupted = 0u64;
 
     for (path, entry) in &snapshot.files {
-        if manifest::blob_exists(&cfg.settings.repo_path, &entry.hash) {
-            ok += 1;
-        } else {
-            missing += 1;
-            eprintln!(
-                "  {} missing blob for: {} ({})",
-                colored::Colorize::red("✗"),
-                path,
-                hasher::short_hash(&entry.hash, 12),
-            );
+        match parity::inspect(repo.as_ref(), &entry.hash)? {
+            parity::ParityStatus::Intact => ok += 1,
+            parity::ParityStatus::Recoverable => {
+                recoverable += 1;
+                eprintln!(
+                    "  {} damaged blob for: {} ({}), recoverable from parity",
+                    colored::Colorize::yellow("!"),
+                    path,
+                    hasher::short_hash(&entry.hash, 12),
+                );
+            }
+            parity::ParityStatus::Unrecoverable => {
+                corrupted += 1;
+                eprintln!(
+                    "  {} corrupted blob for: {} ({})",
+                    colored::Colorize::red("✗"),
+                    path,
+                    hasher::short_hash(&entry.hash, 12),
+                );
+            }
+            parity::ParityStatus::Absent if manifest::blob_exists(repo.as_ref(), &entry.hash)? => {
+                ok += 1
+            }
+            parity::ParityStatus::Absent => {
+                missing += 1;
+                eprintln!(
+                    "  {} missing blob for: {} ({})",
+                    colored::Colorize::red("✗"),
+                    path,
+                    hasher::short_hash(&entry.hash, 12),
+                );
+            }
         }
     }
 
     eprintln!();
-    if missing == 0 {
+    if missing == 0 && corrupted == 0 && recoverable == 0 {
         eprintln!(
             "  {} All {} blobs verified",
             colored::Colorize::green("✓"),
@@ -444,40 +1289,296 @@ fn cmd_verify(cli: &Cli, snapshot_id: &str) -> error::Result<()> {
         );
     } else {
         eprintln!(
-            "  {} {ok} ok, {missing} missing",
+            "  {} {ok} ok, {recoverable} recoverable, {corrupted} corrupted, {missing} missing",
             colored::Colorize::red("✗"),
         );
+        if recoverable > 0 {
+            eprintln!("  Run `but-next repair` to rebuild damaged blobs from parity.");
+        }
     }
 
     Ok(())
 }
 
-fn cmd_watch(cli: &Cli, password: Option<&str>) -> error::Result<()> {
+fn cmd_check(cli: &Cli, password: Option<&str>) -> error::Result<()> {
+    let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
+    let password = password
+        .map(String::from)
+        .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
+
+    print_header("Check");
+    eprintln!("  Repository: {}", repo.location());
+
+    let report = check::check_repository(repo.as_ref(), password.as_deref())?;
+    report.print();
+    report.ensure_healthy()?;
+
+    eprintln!("  {} No problems found", colored::Colorize::green("✓"));
+    Ok(())
+}
+
+fn cmd_repair(cli: &Cli, password: Option<&str>, from_source: bool) -> error::Result<()> {
     let cfg = load_config(cli)?;
+    let repo = backend::open(&cfg.settings)?;
     let password = password
         .map(String::from)
         .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
 
-    let interval = cfg.settings.interval;
+    print_header("Repair");
+    eprintln!("  Repository: {}", repo.location());
+
+    let mut report = check::check_repository(repo.as_ref(), password.as_deref())?;
+    report.print();
+
+    if from_source && (!report.dangling.is_empty() || !report.damaged.is_empty()) {
+        let healed = check::heal_from_source(
+            &cfg,
+            repo.as_ref(),
+            &report,
+            password.as_deref(),
+            cli.verbose,
+        )?;
+        eprintln!();
+        eprintln!(
+            "  {} Healed {} blob(s) from source ({} file(s) hashed), recovered {} file(s) in {} snapshot(s)",
+            colored::Colorize::green("✓"),
+            healed.blobs_healed,
+            healed.files_scanned,
+            healed.files_recovered,
+            healed.snapshots_rewritten,
+        );
+        if healed.blobs_locked > 0 {
+            eprintln!(
+                "  {} {} encrypted blob(s) skipped (no password)",
+                colored::Colorize::yellow("!"),
+                healed.blobs_locked,
+            );
+        }
+        report = check::check_repository(repo.as_ref(), password.as_deref())?;
+    }
+
+    if report.is_healthy() {
+        eprintln!("  {} Nothing to repair", colored::Colorize::green("✓"));
+        return Ok(());
+    }
+
+    let repaired = check::repair_repository(repo.as_ref(), &report, cli.verbose)?;
+
+    eprintln!();
+    if repaired.blobs_rebuilt > 0 {
+        eprintln!(
+            "  {} Rebuilt {} blob(s) from parity",
+            colored::Colorize::green("✓"),
+            repaired.blobs_rebuilt,
+        );
+    }
+    eprintln!(
+        "  {} Quarantined {} manifest(s), {} blob(s), {} stray file(s)",
+        colored::Colorize::green("✓"),
+        repaired.manifests_quarantined,
+        repaired.blobs_quarantined,
+        repaired.stray_quarantined,
+    );
+    eprintln!(
+        "  {} Marked {} file(s) damaged across {} snapshot(s)",
+        colored::Colorize::green("✓"),
+        repaired.files_marked_damaged,
+        repaired.snapshots_rewritten,
+    );
+
+    Ok(())
+}
+
+/// Parses a size argument such as `512M` for clap.
+fn parse_size_arg(value: &str) -> Result<u64, String> {
+    filter::parse_size(value).map_err(|e| e.to_string())
+}
+
+fn cmd_serve(
+    cli: &Cli,
+    repository: Option<&str>,
+    options: server::ServeOptions,
+    clients: &Path,
+) -> error::Result<()> {
+    // A dedicated server needs no backup targets, so a missing config is fine
+    let settings = match load_config(cli) {
+        Ok(cfg) => cfg.settings,
+        Err(error::ButError::Config(error::ConfigError::NotFound { .. })) => {
+            config::Settings::default()
+        }
+        Err(e) => return Err(e),
+    };
+    let repo = match repository {
+        Some(url) => backend::open_url(url, &settings)?,
+        None => backend::open(&settings)?,
+    };
+    let clients = server::load_clients(clients)?;
+
+    print_header("Serve");
+    eprintln!("  Repository: {}", repo.location());
+    eprintln!(
+        "  {} Listening on {}://{} for {} client(s){}",
+        colored::Colorize::cyan("▶"),
+        if options.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        },
+        options.listen,
+        clients.len(),
+        if options.append_only {
+            " (append-only)"
+        } else {
+            ""
+        },
+    );
+
+    server::serve(repo, clients, options)
+}
+
+fn cmd_add_client(
+    clients: &Path,
+    name: &str,
+    password: Option<&str>,
+    append_only: bool,
+) -> error::Result<()> {
+    let password = password
+        .map(String::from)
+        .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok())
+        .ok_or_else(|| {
+            anyhow::anyhow!("a password is required (--password or BUT_NEXT_PASSWORD)")
+        })?;
+
+    server::add_client(clients, name, &password, append_only)?;
     eprintln!(
-        "  {} Watching for changes every {}s (Ctrl+C to stop)",
-        colored::Colorize::cyan("👁"),
-        interval,
+        "{} Added client '{name}'{} to {}",
+        colored::Colorize::green("✓"),
+        if append_only { " (append-only)" } else { "" },
+        clients.display(),
     );
+    Ok(())
+}
+
+fn cmd_watch(
+    cli: &Cli,
+    password: Option<&str>,
+    poll: bool,
+    socket: Option<&Path>,
+) -> error::Result<()> {
+    let password = password
+        .map(String::from)
+        .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
+
+    let opts = backup::BackupOptions {
+        password: password.as_deref(),
+        verbose: cli.verbose,
+        ..Default::default()
+    };
+
+    let socket = socket.map_or_else(control::default_socket, Path::to_path_buf);
+    watch::watch(config_path(cli)?, &opts, poll, &socket)
+}
+
+fn cmd_daemon(cli: &Cli, password: Option<&str>) -> error::Result<()> {
+    let password = password
+        .map(String::from)
+        .or_else(|| std::env::var("BUT_NEXT_PASSWORD").ok());
+
+    print_header("Daemon");
+
+    let opts = backup::BackupOptions {
+        password: password.as_deref(),
+        verbose: cli.verbose,
+        ..Default::default()
+    };
 
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(interval));
+    daemon::run(config_path(cli)?, &opts)
+}
+
+fn cmd_ctl(socket: Option<&Path>, action: &CtlAction) -> error::Result<()> {
+    let socket = socket.map_or_else(control::default_socket, Path::to_path_buf);
+    let request = match action {
+        CtlAction::Status => control::Request::Status,
+        CtlAction::Trigger { target } => control::Request::Trigger {
+            target: target.clone(),
+        },
+        CtlAction::Pause => control::Request::Pause,
+        CtlAction::Resume => control::Request::Resume,
+        CtlAction::Shutdown => control::Request::Shutdown,
+    };
+
+    match control::send(&socket, &request)? {
+        control::Response::Status(status) => print_watch_status(&status),
+        control::Response::Done { message } => {
+            eprintln!("{} {message}", colored::Colorize::green("✓"));
+        }
+        control::Response::Error { message } => {
+            return Err(anyhow::anyhow!(message).into());
+        }
+    }
+    Ok(())
+}
+
+fn print_watch_status(status: &control::Status) {
+    eprintln!(
+        "  {} (pid {})",
+        if status.paused {
+            colored::Colorize::yellow("Paused")
+        } else {
+            colored::Colorize::green("Watching")
+        },
+        status.pid,
+    );
+    if let Some(current) = &status.current {
         eprintln!(
-            "\n  {} {}",
-            colored::Colorize::dimmed("───"),
-            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            "  Backing up {}: {}/{} files ({})",
+            current.target.as_deref().unwrap_or_default(),
+            current.files_done,
+            current.files_total,
+            backup::format_size(current.bytes_done),
         );
-        backup::backup_all(&cfg, password.as_deref(), cli.verbose)?;
+    }
+    eprintln!();
+
+    for (name, target) in &status.targets {
+        let state = if target.pending { "pending" } else { "idle" };
+        let last = match &target.last {
+            None => "no backup yet".to_string(),
+            Some(last) => {
+                let when = local_time(&last.finished_at).format("%Y-%m-%d %H:%M:%S");
+                match (&last.snapshot, &last.error) {
+                    (_, Some(e)) => format!("{} {when}: {e}", colored::Colorize::red("✗")),
+                    (Some(id), None) => format!(
+                        "{} {when}: {}",
+                        colored::Colorize::green("✓"),
+                        &id[..id.len().min(manifest::SHORT_ID_LEN)],
+                    ),
+                    (None, None) => format!("– {when}: unchanged"),
+                }
+            }
+        };
+        eprintln!("  {name:16}  {:8}  {state:8}  {last}", target.mode);
+        if let Some(retry_at) = &target.retry_at {
+            eprintln!(
+                "  {:16}  retrying at {}",
+                "",
+                local_time(retry_at).format("%H:%M:%S"),
+            );
+        }
     }
 }
 
 // ─── Helpers ────────────────────────────────────────────────────────────────
 
+/// The config file to use, for commands that re-read it while running.
+fn config_path(cli: &Cli) -> error::Result<PathBuf> {
+    match &cli.config {
+        Some(path) => Ok(path.clone()),
+        None => config::find_config(),
+    }
+}
+
 fn load_config(cli: &Cli) -> error::Result<config::Config> {
     if let Some(path) = &cli.config {
         config::load_config_from(path)
@@ -486,6 +1587,11 @@ fn load_config(cli: &Cli) -> error::Result<config::Config> {
     }
 }
 
+/// Converts a stored (UTC) time to local time for display.
+fn local_time(time: &chrono::DateTime<chrono::Utc>) -> chrono::DateTime<chrono::Local> {
+    time.with_timezone(&chrono::Local)
+}
+
 fn print_header(action: &str) {
     eprintln!();
     eprintln!(
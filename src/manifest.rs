@@ -10,37 +10,78 @@
 //! ```text
 //! .but/
 //! ├── snapshots/
-//! │   ├── 20240101-120000-documents.json
-//! │   └── 20240101-130000-documents.json
+//! │   ├── 3f9c0d51a2e87b64c1d09a7e5b2f4c86.json
+//! │   └── 20240101-130000-documents.json   (ID format before random IDs)
 //! ├── blobs/
 //! │   ├── a1/
 //! │   │   └── b2c3d4e5f6...   (compressed file content)
 //! │   ├── ff/
 //! │   │   └── 0011aabb...
 //! │   └── ...
-//! └── lock
+//! ├── parity/              (optional Reed-Solomon parity, same sharding)
+//! ├── quarantine/          (corrupted data moved aside by `repair`)
 //! ```
+//!
+//! The layout describes backend keys; see [`crate::backend`] for where they
+//! are stored.
+//!
+//! ## Snapshot Selectors
+//!
+//! Commands that take a snapshot accept any of these, resolved by
+//! [`resolve_snapshot`]:
+//!
+//! | Selector                  | Resolves to                                        |
+//! |---------------------------|----------------------------------------------------|
+//! | `3f9c0d51`                | the snapshot whose ID starts with this (unique)    |
+//! | `latest`                  | the newest snapshot                                |
+//! | `documents`, `documents:latest` | the newest snapshot of a target              |
+//! | `@2024-05-01`, `documents@2024-05-01 18:00` | the newest snapshot taken at or before a time (a date alone means the end of that day) |
+//! | `tag:release`, `documents:tag:release` | the newest snapshot with a tag        |
+//! | `<selector>~N`            | N snapshots before it; see below                   |
+//!
+//! `~N` counts back through the snapshots the selector chooses from: those of
+//! its target when it names one (or is an ID), otherwise all targets, so
+//! `documents~1` is the previous `documents` snapshot while `latest~1` is the
+//! second newest snapshot of any target.
 
+use crate::backend::Backend;
 use crate::config::CompressionKind;
-use chrono::{DateTime, Local};
+use crate::error::BackendError;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// Minimum length of the ID prefixes shown by `list`.
+pub const SHORT_ID_LEN: usize = 8;
 
 /// A complete snapshot of a backup target at a specific point in time.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Snapshot {
-    /// Unique snapshot identifier (timestamp + target name).
+    /// Unique snapshot identifier: 32 random hex digits. Older snapshots use
+    /// timestamp + target name, which can collide within the same second.
     pub id: String,
 
     /// Name of the backup target (from config).
     pub target_name: String,
 
-    /// Source directory that was backed up.
+    /// Source directory that was backed up; the filesystem root when the
+    /// target has several sources.
     pub source_path: PathBuf,
 
-    /// When the snapshot was created.
-    pub created_at: DateTime<Local>,
+    /// Sources of a target with several, each stored under its absolute path
+    /// relative to `source_path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<PathBuf>,
+
+    /// When the snapshot was created, in UTC.
+    pub created_at: DateTime<Utc>,
+
+    /// Offset from UTC in seconds of the clock on the host that created the
+    /// snapshot. Together with `created_at` it gives the wall-clock time there.
+    /// Only missing while parsing manifests that predate it.
+    #[serde(default)]
+    pub utc_offset: Option<i32>,
 
     /// Compression algorithm used for blobs in this snapshot.
     pub compression: CompressionKind,
@@ -53,6 +94,49 @@ pub struct Snapshot {
 
     /// Summary statistics computed after the backup completes.
     pub stats: SnapshotStats,
+
+    /// Host the backup ran on.
+    #[serde(default)]
+    pub hostname: String,
+
+    /// User the backup ran as.
+    #[serde(default)]
+    pub username: String,
+
+    /// but-next version that wrote the snapshot.
+    #[serde(default)]
+    pub version: String,
+
+    /// Command line of the process that created the snapshot.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command_line: Vec<String>,
+
+    /// Previous snapshot of the same target, if there was one; for an
+    /// unchanged snapshot, the snapshot whose files it shares.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+
+    /// Set when no file changed since `parent`; no data was stored, and the
+    /// manifest lists no files of its own (they are filled in from `parent`
+    /// when it is read).
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unchanged: bool,
+
+    /// User-supplied labels (`--tag`, `but-next tag`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+
+    /// User-supplied description (`--message`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// Protects the snapshot from `prune` and deletion.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+
+    /// When set, the pin lapses at this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_until: Option<DateTime<Utc>>,
 }
 
 /// Metadata for a single file within a snapshot.
@@ -75,6 +159,10 @@ pub struct FileEntry {
 
     /// Whether this blob was already present (deduplicated).
     pub deduplicated: bool,
+
+    /// Set by `repair` when the blob is missing or corrupted and could not be recovered.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub damaged: bool,
 }
 
 /// Aggregate statistics for a snapshot.
@@ -103,13 +191,21 @@ pub struct SnapshotStats {
 
     /// Backup duration in milliseconds.
     pub duration_ms: u64,
+
+    /// Files left out by the target's size and age limits.
+    #[serde(default)]
+    pub skipped_files: u64,
+
+    /// Directories on other filesystems left out by `one_file_system`.
+    #[serde(default)]
+    pub skipped_mounts: u64,
 }
 
 impl Snapshot {
-    /// Generates a snapshot ID from the current time and target name.
-    pub fn generate_id(target_name: &str) -> String {
-        let now = Local::now();
-        format!("{}-{}", now.format("%Y%m%d-%H%M%S"), target_name)
+    /// Generates a random 128-bit snapshot ID.
+    pub fn generate_id() -> String {
+        let bytes: [u8; 16] = rand::random();
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
     }
 
     /// Creates a new empty snapshot.
@@ -119,16 +215,84 @@ impl Snapshot {
         compression: CompressionKind,
         encrypted: bool,
     ) -> Self {
+        let now = Local::now();
         Self {
-            id: Self::generate_id(target_name),
+            id: Self::generate_id(),
             target_name: target_name.to_string(),
             source_path,
-            created_at: Local::now(),
+            sources: Vec::new(),
+            created_at: now.with_timezone(&Utc),
+            utc_offset: Some(now.offset().local_minus_utc()),
             compression,
             encrypted,
             files: BTreeMap::new(),
             stats: SnapshotStats::default(),
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            username: current_username(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            command_line: std::env::args().collect(),
+            parent: None,
+            unchanged: false,
+            tags: Vec::new(),
+            message: None,
+            pinned: false,
+            pinned_until: None,
+        }
+    }
+
+    /// Returns true if the snapshot is pinned and the pin has not expired.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned && self.pinned_until.map_or(true, |until| Utc::now() < until)
+    }
+
+    /// Creation time as shown by the clock of the host that made the snapshot.
+    pub fn created_at_origin(&self) -> DateTime<FixedOffset> {
+        let offset = self
+            .utc_offset
+            .and_then(FixedOffset::east_opt)
+            .unwrap_or_else(|| Utc.fix());
+        self.created_at.with_timezone(&offset)
+    }
+
+    /// Returns true if both snapshots hold the same paths with the same
+    /// content and metadata, stored the same way, and none of `other`'s
+    /// files is damaged.
+    pub fn same_files(&self, other: &Snapshot) -> bool {
+        self.compression == other.compression
+            && self.encrypted == other.encrypted
+            && self.files.len() == other.files.len()
+            && self
+                .files
+                .iter()
+                .zip(&other.files)
+                .all(|((path, a), (other_path, b))| {
+                    path == other_path
+                        && a.hash == b.hash
+                        && a.size == b.size
+                        && a.permissions == b.permissions
+                        && a.modified == b.modified
+                        && !b.damaged
+                })
+    }
+
+    /// Returns true if the snapshot carries `tag`.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Adds tags not already present, keeping the list sorted.
+    pub fn add_tags(&mut self, tags: &[String]) {
+        for tag in tags {
+            if !self.has_tag(tag) {
+                self.tags.push(tag.clone());
+            }
         }
+        self.tags.sort();
+    }
+
+    /// Removes the given tags; tags not present are ignored.
+    pub fn remove_tags(&mut self, tags: &[String]) {
+        self.tags.retain(|t| !tags.contains(t));
     }
 
     /// Adds a file entry to the snapshot.
@@ -155,114 +319,334 @@ impl Snapshot {
 
     /// Deserializes a snapshot from JSON.
     pub fn from_json(json: &str) -> anyhow::Result<Self> {
-        serde_json::from_str(json).map_err(|e| anyhow::anyhow!("failed to parse snapshot: {e}"))
+        let parse_error = |e| anyhow::anyhow!("failed to parse snapshot: {e}");
+        let mut snapshot: Self = serde_json::from_str(json).map_err(parse_error)?;
+
+        // Manifests written before `utc_offset` existed stored `created_at`
+        // in local time with an explicit offset; recover it from there
+        if snapshot.utc_offset.is_none() {
+            #[derive(Deserialize)]
+            struct LocalTime {
+                created_at: DateTime<FixedOffset>,
+            }
+            let local: LocalTime = serde_json::from_str(json).map_err(parse_error)?;
+            snapshot.utc_offset = Some(local.created_at.offset().local_minus_utc());
+        }
+        Ok(snapshot)
     }
 }
 
 // ─── Repository Operations ──────────────────────────────────────────────────
 
-/// Initializes the repository directory structure.
-pub fn init_repo(repo_path: &Path) -> anyhow::Result<()> {
-    let dirs = ["snapshots", "blobs"];
-    for dir in &dirs {
-        std::fs::create_dir_all(repo_path.join(dir))?;
-    }
-    Ok(())
+/// Returns the backend key for a blob given its hash, using 2-char sharding.
+pub fn blob_key(hash: &str) -> String {
+    let (prefix, suffix) = crate::hasher::shard_path(hash);
+    format!("blobs/{prefix}/{suffix}")
 }
 
-/// Returns the filesystem path for a blob given its hash, using 2-char sharding.
-pub fn blob_path(repo_path: &Path, hash: &str) -> PathBuf {
+/// Returns the backend key of a blob's parity file.
+pub fn parity_key(hash: &str) -> String {
     let (prefix, suffix) = crate::hasher::shard_path(hash);
-    repo_path.join("blobs").join(prefix).join(suffix)
+    format!("parity/{prefix}/{suffix}")
+}
+
+/// Returns the backend key of a snapshot manifest.
+pub fn snapshot_key(id: &str) -> String {
+    format!("snapshots/{id}.json")
 }
 
 /// Checks whether a blob with the given hash already exists in the repository.
-pub fn blob_exists(repo_path: &Path, hash: &str) -> bool {
-    blob_path(repo_path, hash).exists()
+pub fn blob_exists(repo: &dyn Backend, hash: &str) -> anyhow::Result<bool> {
+    Ok(repo.exists(&blob_key(hash))?)
 }
 
-/// Writes a blob to the content-addressable store, creating shard directories as needed.
-pub fn store_blob(repo_path: &Path, hash: &str, data: &[u8]) -> anyhow::Result<()> {
-    let path = blob_path(repo_path, hash);
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    std::fs::write(&path, data)?;
+/// Writes a blob to the content-addressable store.
+pub fn store_blob(repo: &dyn Backend, hash: &str, data: &[u8]) -> anyhow::Result<()> {
+    repo.write(&blob_key(hash), data)?;
     Ok(())
 }
 
 /// Reads a blob from the content-addressable store.
-pub fn read_blob(repo_path: &Path, hash: &str) -> anyhow::Result<Vec<u8>> {
-    let path = blob_path(repo_path, hash);
-    std::fs::read(&path).map_err(|e| anyhow::anyhow!("failed to read blob {}: {e}", hash))
+///
+/// If the blob has a parity file and its stored bytes are damaged (or the
+/// blob is missing), the corrected content is reconstructed transparently.
+pub fn read_blob(repo: &dyn Backend, hash: &str) -> anyhow::Result<Vec<u8>> {
+    let data = repo.read(&blob_key(hash));
+    if let Some(parity) = crate::parity::read_parity(repo, hash)? {
+        let stored = data.as_deref().ok();
+        if let Ok(crate::parity::Reconstruction::Repaired(fixed)) =
+            crate::parity::reconstruct(stored, &parity)
+        {
+            return Ok(fixed);
+        }
+    }
+    // Backend errors name the blob's key
+    Ok(data?)
 }
 
-/// Saves a snapshot manifest to the snapshots directory.
-pub fn save_snapshot(repo_path: &Path, snapshot: &Snapshot) -> anyhow::Result<PathBuf> {
-    let filename = format!("{}.json", snapshot.id);
-    let path = repo_path.join("snapshots").join(&filename);
-    let json = snapshot.to_json()?;
-    std::fs::write(&path, json)?;
-    Ok(path)
+/// Whether an error from [`read_blob`] means the blob doesn't exist, rather
+/// than that the repository couldn't be reached.
+pub fn is_missing(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<BackendError>(),
+        Some(BackendError::NotFound(_))
+    )
+}
+
+/// Saves a snapshot manifest, returning its key. The files of an unchanged
+/// snapshot are left to its parent.
+pub fn save_snapshot(repo: &dyn Backend, snapshot: &Snapshot) -> anyhow::Result<String> {
+    let key = snapshot_key(&snapshot.id);
+    let json = if snapshot.unchanged && snapshot.parent.is_some() {
+        Snapshot {
+            files: BTreeMap::new(),
+            ..snapshot.clone()
+        }
+        .to_json()?
+    } else {
+        snapshot.to_json()?
+    };
+    repo.write(&key, json.as_bytes())?;
+    Ok(key)
 }
 
 /// Lists all snapshots in the repository, sorted by creation time.
-pub fn list_snapshots(repo_path: &Path) -> anyhow::Result<Vec<Snapshot>> {
-    let snapshots_dir = repo_path.join("snapshots");
-    if !snapshots_dir.exists() {
-        return Ok(Vec::new());
+///
+/// Manifests that fail to parse are reported on stderr and skipped; use
+/// [`scan_snapshots`] to collect them instead.
+pub fn list_snapshots(repo: &dyn Backend) -> anyhow::Result<Vec<Snapshot>> {
+    let (snapshots, corrupted) = scan_snapshots(repo)?;
+    for (key, e) in &corrupted {
+        eprintln!("warning: skipping corrupted snapshot {key}: {e}");
     }
+    Ok(snapshots)
+}
+
+/// The newest snapshot of each target, keyed by target name.
+pub fn latest_snapshots(repo: &dyn Backend) -> anyhow::Result<BTreeMap<String, Snapshot>> {
+    Ok(list_snapshots(repo)?
+        .into_iter()
+        .map(|s| (s.target_name.clone(), s))
+        .collect())
+}
+
+/// A manifest that could not be read or parsed: its key and the reason.
+pub type CorruptedManifest = (String, String);
 
+/// Reads every manifest in the repository, returning the parsed snapshots
+/// (sorted by creation time) alongside the manifests that could not be parsed.
+pub fn scan_snapshots(
+    repo: &dyn Backend,
+) -> anyhow::Result<(Vec<Snapshot>, Vec<CorruptedManifest>)> {
     let mut snapshots = Vec::new();
-    for entry in std::fs::read_dir(&snapshots_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().is_some_and(|ext| ext == "json") {
-            let json = std::fs::read_to_string(&path)?;
-            match Snapshot::from_json(&json) {
-                Ok(snap) => snapshots.push(snap),
-                Err(e) => eprintln!(
-                    "warning: skipping corrupted snapshot {}: {e}",
-                    path.display()
-                ),
+    let mut corrupted = Vec::new();
+    for object in repo.list("snapshots/")? {
+        if !object.key.ends_with(".json") {
+            continue;
+        }
+        let parsed = repo
+            .read(&object.key)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Snapshot::from_json(&String::from_utf8_lossy(&bytes)));
+        match parsed {
+            Ok(snap) => snapshots.push(snap),
+            Err(e) => corrupted.push((object.key, e.to_string())),
+        }
+    }
+
+    snapshots.sort_by_key(|s| s.created_at);
+
+    // Unchanged snapshots take their files from their parent, which is
+    // never unchanged itself
+    let files: HashMap<String, BTreeMap<String, FileEntry>> = snapshots
+        .iter()
+        .filter(|s| !s.unchanged)
+        .map(|s| (s.id.clone(), s.files.clone()))
+        .collect();
+    let mut resolved = Vec::with_capacity(snapshots.len());
+    for mut snap in snapshots {
+        if let (true, Some(parent)) = (snap.unchanged, &snap.parent) {
+            match files.get(parent) {
+                Some(parent_files) => snap.files = parent_files.clone(),
+                None => {
+                    let reason = format!("parent snapshot {parent} is missing");
+                    corrupted.push((snapshot_key(&snap.id), reason));
+                    continue;
+                }
             }
         }
+        resolved.push(snap);
     }
+    Ok((resolved, corrupted))
+}
 
-    snapshots.sort_by(|a, b| a.created_at.cmp(&b.created_at));
-    Ok(snapshots)
+/// The unchanged snapshots among `snapshots` whose files are those of
+/// `snapshot`.
+pub fn dependents<'a>(
+    snapshots: &'a [Snapshot],
+    snapshot: &'a Snapshot,
+) -> impl Iterator<Item = &'a Snapshot> {
+    snapshots
+        .iter()
+        .filter(|s| s.unchanged && s.parent.as_deref() == Some(snapshot.id.as_str()))
 }
 
-/// Lists snapshots filtered by target name.
-pub fn list_snapshots_for_target(repo_path: &Path, target: &str) -> anyhow::Result<Vec<Snapshot>> {
-    let all = list_snapshots(repo_path)?;
-    Ok(all
-        .into_iter()
-        .filter(|s| s.target_name == target)
-        .collect())
+/// Resolves a snapshot selector (see the module docs) against the repository.
+pub fn resolve_snapshot(repo: &dyn Backend, selector: &str) -> anyhow::Result<Snapshot> {
+    let all = list_snapshots(repo)?;
+    select_snapshot(&all, selector).cloned()
 }
 
-/// Finds a specific snapshot by ID (exact or prefix match).
-pub fn find_snapshot(repo_path: &Path, id_prefix: &str) -> anyhow::Result<Option<Snapshot>> {
-    let all = list_snapshots(repo_path)?;
-    let matches: Vec<_> = all
-        .into_iter()
-        .filter(|s| s.id.starts_with(id_prefix))
-        .collect();
+/// Resolves a snapshot selector against `snapshots`, which must be sorted
+/// oldest first as returned by [`list_snapshots`].
+pub fn select_snapshot<'a>(
+    snapshots: &'a [Snapshot],
+    selector: &str,
+) -> anyhow::Result<&'a Snapshot> {
+    let not_found = || anyhow::anyhow!("no snapshot matches '{selector}'");
+
+    let (base, back) = match selector.rsplit_once('~') {
+        Some((base, n)) => (
+            base,
+            n.parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("invalid selector '{selector}': expected ~N"))?,
+        ),
+        None => (selector, 0),
+    };
+
+    let of_target = |target: Option<&str>| -> Vec<&'a Snapshot> {
+        snapshots
+            .iter()
+            .filter(|s| target.map_or(true, |t| s.target_name == t))
+            .collect()
+    };
+
+    let (pool, index) = if let Some((target, time)) = base.split_once('@') {
+        let target = Some(target).filter(|t| !t.is_empty());
+        let time = parse_time(time)
+            .ok_or_else(|| anyhow::anyhow!("invalid time in selector '{selector}'"))?;
+        let pool = of_target(target);
+        let index = pool.iter().rposition(|s| s.created_at <= time);
+        (pool, index)
+    } else {
+        let (target, rest) = match base.split_once(':') {
+            Some((target, rest)) if !base.starts_with("tag:") => (Some(target), rest),
+            _ => (None, base),
+        };
+        if let Some(tag) = rest.strip_prefix("tag:") {
+            let pool = of_target(target);
+            let index = pool.iter().rposition(|s| s.has_tag(tag));
+            (pool, index)
+        } else if rest == "latest"
+            || target.is_none() && snapshots.iter().any(|s| s.target_name == rest)
+        {
+            if target.is_none() && snapshots.iter().any(|s| s.id.starts_with(rest)) {
+                return Err(anyhow::anyhow!(
+                    "'{rest}' is both a target and a snapshot ID prefix; \
+                     use {rest}:latest or a longer ID"
+                ));
+            }
+            let target = target.or(Some(rest).filter(|r| *r != "latest"));
+            let pool = of_target(target);
+            let index = pool.len().checked_sub(1);
+            (pool, index)
+        } else if target.is_some() {
+            return Err(anyhow::anyhow!(
+                "invalid selector '{selector}': expected <target>:latest or <target>:tag:<tag>"
+            ));
+        } else {
+            let matches: Vec<&Snapshot> = snapshots
+                .iter()
+                .filter(|s| s.id.starts_with(rest))
+                .collect();
+            let found = match matches.as_slice() {
+                [] => return Err(not_found()),
+                [one] => *one,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "ambiguous snapshot prefix '{rest}': matched {} snapshots",
+                        matches.len()
+                    ))
+                }
+            };
+            let pool = of_target(Some(&found.target_name));
+            let index = pool.iter().position(|s| s.id == found.id);
+            (pool, index)
+        }
+    };
 
-    match matches.len() {
-        0 => Ok(None),
-        1 => Ok(Some(matches.into_iter().next().unwrap())),
-        n => Err(anyhow::anyhow!(
-            "ambiguous snapshot prefix '{id_prefix}': matched {n} snapshots"
-        )),
-    }
+    let index = index.ok_or_else(not_found)?;
+    let index = index.checked_sub(back).ok_or_else(|| {
+        anyhow::anyhow!("'{selector}': only {index} older snapshot(s) to go back through")
+    })?;
+    Ok(pool[index])
+}
+
+/// Shortest unambiguous prefix of each snapshot ID (at least
+/// [`SHORT_ID_LEN`] characters), keyed by full ID.
+pub fn short_ids(snapshots: &[Snapshot]) -> HashMap<String, String> {
+    let mut ids: Vec<&str> = snapshots.iter().map(|s| s.id.as_str()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let common = |a: &str, b: &str| a.bytes().zip(b.bytes()).take_while(|(x, y)| x == y).count();
+    ids.iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let prev = i.checked_sub(1).map_or(0, |j| common(ids[j], id));
+            let next = ids.get(i + 1).map_or(0, |other| common(other, id));
+            let len = (prev.max(next) + 1).max(SHORT_ID_LEN).min(id.len());
+            let short = id.get(..len).unwrap_or(id);
+            (id.to_string(), short.to_string())
+        })
+        .collect()
 }
 
-/// Deletes a snapshot and any orphaned blobs.
-pub fn delete_snapshot(repo_path: &Path, snapshot: &Snapshot) -> anyhow::Result<u64> {
+/// Parses `2024-05-01` (end of that day), `2024-05-01 18:00` or
+/// `2024-05-01T18:00:30` as local time.
+fn parse_time(input: &str) -> Option<DateTime<Utc>> {
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|fmt| NaiveDateTime::parse_from_str(input, fmt).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(input, "%Y-%m-%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(23, 59, 59))
+    })?;
+    naive
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// Deletes a snapshot and any orphaned blobs. Pinned snapshots, and those
+/// unchanged snapshots take their files from, are refused.
+pub fn delete_snapshot(repo: &dyn Backend, snapshot: &Snapshot) -> anyhow::Result<u64> {
+    if snapshot.is_pinned() {
+        return Err(anyhow::anyhow!(
+            "snapshot {} is pinned; unpin it first",
+            snapshot.id
+        ));
+    }
+
+    let all_snapshots = list_snapshots(repo)?;
+    let dependents: Vec<&str> = dependents(&all_snapshots, snapshot)
+        .map(|s| s.id.as_str())
+        .collect();
+    if !dependents.is_empty() {
+        return Err(anyhow::anyhow!(
+            "snapshot {} holds the files of unchanged snapshot(s) {}; delete those first",
+            snapshot.id,
+            dependents.join(", ")
+        ));
+    }
+
     // Collect all blob hashes referenced by other snapshots
-    let all_snapshots = list_snapshots(repo_path)?;
     let mut referenced_hashes = std::collections::HashSet::new();
 
     for snap in &all_snapshots {
@@ -274,23 +658,207 @@ pub fn delete_snapshot(repo_path: &Path, snapshot: &Snapshot) -> anyhow::Result<
         }
     }
 
+    // Remove the manifest first so a refused or interrupted deletion never
+    // leaves a snapshot pointing at missing blobs
+    repo.delete(&snapshot_key(&snapshot.id))?;
+
     // Delete orphaned blobs (only referenced by the snapshot being deleted)
     let mut freed_bytes = 0u64;
     for entry in snapshot.files.values() {
         if !referenced_hashes.contains(&entry.hash) {
-            let path = blob_path(repo_path, &entry.hash);
-            if path.exists() {
-                freed_bytes += std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
-                let _ = std::fs::remove_file(&path);
+            let key = blob_key(&entry.hash);
+            if let Some(size) = repo.size(&key)? {
+                freed_bytes += size;
+                repo.delete(&key)?;
+            }
+            let parity = parity_key(&entry.hash);
+            if let Some(size) = repo.size(&parity)? {
+                freed_bytes += size;
+                repo.delete(&parity)?;
             }
         }
     }
 
-    // Delete the snapshot manifest
-    let manifest_path = repo_path
-        .join("snapshots")
-        .join(format!("{}.json", snapshot.id));
-    let _ = std::fs::remove_file(&manifest_path);
-
     Ok(freed_bytes)
 }
+
+/// Moves an object under the repository's `quarantine/` prefix, keeping its
+/// original key so it can be inspected later. Returns the new key.
+pub fn quarantine(repo: &dyn Backend, key: &str) -> anyhow::Result<String> {
+    let dest = format!("quarantine/{key}");
+    let data = repo.read(key)?;
+    repo.write(&dest, &data)?;
+    repo.delete(key)?;
+    Ok(dest)
+}
+
+/// Lists the hashes of all blobs present in the content-addressable store.
+///
+/// Keys that do not follow the `blobs/<2-char prefix>/<suffix>` layout are
+/// returned separately as stray keys.
+pub fn list_blobs(repo: &dyn Backend) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    let mut hashes = Vec::new();
+    let mut stray = Vec::new();
+
+    for object in repo.list("blobs/")? {
+        let parts: Vec<&str> = object.key.split('/').collect();
+        match parts.as_slice() {
+            ["blobs", prefix, suffix]
+                if prefix.len() == 2 && is_valid_hash(&format!("{prefix}{suffix}")) =>
+            {
+                hashes.push(format!("{prefix}{suffix}"));
+            }
+            _ => stray.push(object.key),
+        }
+    }
+
+    hashes.sort();
+    Ok((hashes, stray))
+}
+
+/// Returns true if `hash` looks like a hex-encoded BLAKE3 digest.
+pub fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Checks that a tag is usable on the command line and in selectors.
+pub fn validate_tag(tag: &str) -> anyhow::Result<()> {
+    if tag.is_empty() || tag.chars().any(|c| c.is_whitespace() || c == ',') {
+        return Err(anyhow::anyhow!(
+            "invalid tag '{tag}': tags must be non-empty without spaces or commas"
+        ));
+    }
+    Ok(())
+}
+
+/// Login name of the current user, or an empty string if unknown.
+fn current_username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn snapshot(target: &str, day: u32, tags: &[&str]) -> Snapshot {
+        let mut snap = Snapshot::new(target, PathBuf::from("/"), CompressionKind::None, false);
+        let local = Local.with_ymd_and_hms(2024, 5, day, 12, 0, 0).unwrap();
+        snap.created_at = local.with_timezone(&Utc);
+        snap.id = format!("{}-{target}", local.format("%Y%m%d-%H%M%S"));
+        snap.tags = tags.iter().map(|t| t.to_string()).collect();
+        snap
+    }
+
+    #[test]
+    fn selectors() {
+        let snaps = vec![
+            snapshot("docs", 1, &["release"]),
+            snapshot("photos", 2, &[]),
+            snapshot("docs", 3, &[]),
+            snapshot("docs", 5, &[]),
+            snapshot("photos", 6, &["release"]),
+        ];
+        let id = |selector: &str| select_snapshot(&snaps, selector).map(|s| s.id.clone());
+
+        assert_eq!(id("latest").unwrap(), "20240506-120000-photos");
+        assert_eq!(id("docs").unwrap(), "20240505-120000-docs");
+        assert_eq!(id("docs:latest").unwrap(), "20240505-120000-docs");
+        assert_eq!(id("docs~2").unwrap(), "20240501-120000-docs");
+        assert_eq!(id("latest~1").unwrap(), "20240505-120000-docs");
+        assert_eq!(id("@2024-05-03").unwrap(), "20240503-120000-docs");
+        assert_eq!(id("@2024-05-03~1").unwrap(), "20240502-120000-photos");
+        assert_eq!(id("docs@2024-05-03~1").unwrap(), "20240501-120000-docs");
+        assert_eq!(
+            id("photos@2024-05-05 23:00").unwrap(),
+            "20240502-120000-photos"
+        );
+        assert_eq!(id("tag:release").unwrap(), "20240506-120000-photos");
+        assert_eq!(id("docs:tag:release").unwrap(), "20240501-120000-docs");
+        assert_eq!(id("20240503").unwrap(), "20240503-120000-docs");
+        assert_eq!(id("20240503~1").unwrap(), "20240501-120000-docs");
+
+        assert!(id("docs~3").is_err());
+        assert!(id("2024050").is_err()); // ambiguous
+        assert!(id("@2024-04-30").is_err());
+        assert!(id("tag:missing").is_err());
+        assert!(id("docs:oldest").is_err());
+        assert!(id("docs~x").is_err());
+
+        // A target named like an ID prefix must be selected explicitly
+        let shadowing = vec![snapshot("docs", 1, &[]), snapshot("2024", 2, &[])];
+        let id = |selector: &str| select_snapshot(&shadowing, selector).map(|s| s.id.clone());
+        assert!(id("2024").is_err());
+        assert_eq!(id("2024:latest").unwrap(), "20240502-120000-2024");
+        assert_eq!(id("20240501").unwrap(), "20240501-120000-docs");
+    }
+
+    #[test]
+    fn tags_stay_sorted_and_unique() {
+        let tags = |list: &[&str]| list.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let mut snap = snapshot("docs", 1, &["release"]);
+        snap.add_tags(&tags(&["weekly", "audit", "release", "audit"]));
+        assert_eq!(snap.tags, ["audit", "release", "weekly"]);
+
+        snap.remove_tags(&tags(&["release", "missing"]));
+        assert_eq!(snap.tags, ["audit", "weekly"]);
+        assert!(!snap.has_tag("release"));
+
+        assert!(validate_tag("pre-upgrade_2").is_ok());
+        assert!(validate_tag("").is_err());
+        assert!(validate_tag("two words").is_err());
+        assert!(validate_tag("a,b").is_err());
+        assert!(validate_tag("tab\t").is_err());
+    }
+
+    #[test]
+    fn legacy_local_timestamps_keep_their_offset() {
+        let snap = Snapshot::new("docs", PathBuf::from("/"), CompressionKind::None, false);
+        let mut json: serde_json::Value = serde_json::from_str(&snap.to_json().unwrap()).unwrap();
+        let object = json.as_object_mut().unwrap();
+        object.remove("utc_offset");
+        object.insert("created_at".into(), "2024-05-01T23:30:00+02:00".into());
+
+        let legacy = Snapshot::from_json(&json.to_string()).unwrap();
+        assert_eq!(
+            legacy.created_at,
+            Utc.with_ymd_and_hms(2024, 5, 1, 21, 30, 0).unwrap()
+        );
+        assert_eq!(legacy.utc_offset, Some(7200));
+        assert_eq!(
+            legacy.created_at_origin().to_rfc3339(),
+            "2024-05-01T23:30:00+02:00"
+        );
+
+        let json = legacy.to_json().unwrap();
+        assert!(json.contains("\"created_at\": \"2024-05-01T21:30:00Z\""));
+        assert_eq!(Snapshot::from_json(&json).unwrap().utc_offset, Some(7200));
+    }
+
+    #[test]
+    fn generated_ids_are_unique_and_shortened() {
+        let mut snaps: Vec<Snapshot> = (0..50)
+            .map(|_| Snapshot::new("docs", PathBuf::from("/"), CompressionKind::None, false))
+            .collect();
+        let short = short_ids(&snaps);
+        assert_eq!(short.len(), 50);
+        for snap in &snaps {
+            assert_eq!(snap.id.len(), 32);
+            assert_eq!(
+                select_snapshot(&snaps, &short[&snap.id]).unwrap().id,
+                snap.id
+            );
+        }
+
+        // Legacy timestamp IDs stay selectable and get prefixes long enough
+        // to tell them apart
+        snaps.truncate(1);
+        snaps.push(snapshot("docs", 1, &[]));
+        snaps.push(snapshot("photos", 1, &[]));
+        let short = short_ids(&snaps);
+        assert_eq!(short["20240501-120000-docs"], "20240501-120000-d");
+        assert_eq!(short[&snaps[0].id].len(), SHORT_ID_LEN);
+    }
+}
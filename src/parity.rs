@@ -0,0 +1,399 @@
+//! # Erasure-Coded Parity
+//!
+//! Optional Reed-Solomon parity for bit-rot protection. Each stored blob is
+//! split into `k` equally sized data shards and `m` parity shards are computed
+//! over them, where `m` is the configured redundancy percentage of `k`. Any
+//! `m` damaged shards can be reconstructed from the rest.
+//!
+//! Parity lives next to the blob store under `parity/`, using the same
+//! 2-char sharding, and covers the blob exactly as stored (after compression
+//! and encryption) so it can be checked without a password.
+//!
+//! ## File format
+//!
+//! ```text
+//! ┌────────────┬──────────┬────────────┬─────┬─────┬─────────────┬──────────────┬─────────────────┐
+//! │ Magic (8B) │ len (8B) │ shard (4B) │ k   │ m   │ header hash │ shard hashes │ parity shards   │
+//! │ BUTPAR1\0  │ u64 LE   │ u32 LE     │ u16 │ u16 │ 32B BLAKE3  │ (k+m) × 32B  │ m × shard bytes │
+//! └────────────┴──────────┴────────────┴─────┴─────┴─────────────┴──────────────┴─────────────────┘
+//! ```
+//!
+//! Per-shard BLAKE3 hashes tell the decoder *which* shards are damaged, which
+//! turns the Reed-Solomon code from error correction into the much stronger
+//! erasure correction.
+
+use crate::backend::Backend;
+use crate::error::{BackendError, Result};
+use crate::manifest;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+const MAGIC: &[u8; 8] = b"BUTPAR1\0";
+
+/// Fixed header size: magic, data length, shard size, k, m.
+const HEADER_LEN: usize = 8 + 8 + 4 + 2 + 2;
+
+const HASH_LEN: usize = 32;
+
+/// Preferred size of a data shard. Small enough that a few bad sectors only
+/// damage a few shards, large enough to keep the hash table overhead low.
+const TARGET_SHARD_SIZE: usize = 4096;
+
+/// Upper bound on data shards; keeps `k + m` within GF(2^8) at 100% redundancy.
+const MAX_DATA_SHARDS: usize = 128;
+
+/// Result of checking a blob against its parity.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reconstruction {
+    /// All shards match their recorded hashes.
+    Intact,
+    /// Some shards were damaged and have been rebuilt; contains the corrected data.
+    Repaired(Vec<u8>),
+    /// More shards are damaged than the parity can rebuild.
+    Unrecoverable,
+}
+
+/// Health of a stored blob as seen through its parity file.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParityStatus {
+    /// No parity file (or an unreadable one) exists for the blob.
+    Absent,
+    Intact,
+    Recoverable,
+    Unrecoverable,
+}
+
+struct Layout {
+    data_len: usize,
+    shard_size: usize,
+    data_shards: usize,
+    parity_shards: usize,
+}
+
+impl Layout {
+    fn for_data(len: usize, redundancy: u8) -> Self {
+        let data_shards = len.div_ceil(TARGET_SHARD_SIZE).clamp(1, MAX_DATA_SHARDS);
+        let shard_size = len.div_ceil(data_shards).max(1);
+        let parity_shards = (data_shards * redundancy as usize).div_ceil(100).max(1);
+        Self {
+            data_len: len,
+            shard_size,
+            data_shards,
+            parity_shards,
+        }
+    }
+
+    fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+}
+
+/// Computes the parity file for `data` at the given redundancy percentage.
+pub fn encode(data: &[u8], redundancy: u8) -> Result<Vec<u8>> {
+    let layout = Layout::for_data(data.len(), redundancy);
+    let mut shards = split_shards(data, &layout);
+    shards.extend((0..layout.parity_shards).map(|_| vec![0u8; layout.shard_size]));
+
+    let rs = ReedSolomon::new(layout.data_shards, layout.parity_shards)
+        .map_err(|e| anyhow::anyhow!("invalid parity layout: {e:?}"))?;
+    rs.encode(&mut shards)
+        .map_err(|e| anyhow::anyhow!("parity encoding failed: {e:?}"))?;
+
+    let mut out = Vec::with_capacity(
+        HEADER_LEN
+            + HASH_LEN * (1 + layout.total_shards())
+            + layout.parity_shards * layout.shard_size,
+    );
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&(layout.data_len as u64).to_le_bytes());
+    out.extend_from_slice(&(layout.shard_size as u32).to_le_bytes());
+    out.extend_from_slice(&(layout.data_shards as u16).to_le_bytes());
+    out.extend_from_slice(&(layout.parity_shards as u16).to_le_bytes());
+    let header_hash = blake3::hash(&out);
+    out.extend_from_slice(header_hash.as_bytes());
+    for shard in &shards {
+        out.extend_from_slice(blake3::hash(shard).as_bytes());
+    }
+    for shard in &shards[layout.data_shards..] {
+        out.extend_from_slice(shard);
+    }
+
+    Ok(out)
+}
+
+/// Checks `data` (or its absence) against a parity file, rebuilding damaged shards.
+pub fn reconstruct(data: Option<&[u8]>, parity: &[u8]) -> Result<Reconstruction> {
+    let layout = parse_header(parity)?;
+    let total = layout.total_shards();
+    let hashes_start = HEADER_LEN + HASH_LEN;
+    let parity_start = hashes_start + HASH_LEN * total;
+    let expected_hash = |i: usize| &parity[hashes_start + HASH_LEN * i..][..HASH_LEN];
+
+    // A truncated blob simply fails the hash check on its short shards; bytes
+    // appended past the recorded length are dropped
+    let data_shards = match data {
+        Some(d) => split_shards(&d[..d.len().min(layout.data_len)], &layout),
+        None => vec![Vec::new(); layout.data_shards],
+    };
+
+    let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(total);
+    for (i, shard) in data_shards.into_iter().enumerate() {
+        let ok =
+            shard.len() == layout.shard_size && blake3::hash(&shard).as_bytes() == expected_hash(i);
+        shards.push(ok.then_some(shard));
+    }
+    for j in 0..layout.parity_shards {
+        let i = layout.data_shards + j;
+        let shard = &parity[parity_start + j * layout.shard_size..][..layout.shard_size];
+        let ok = blake3::hash(shard).as_bytes() == expected_hash(i);
+        shards.push(ok.then(|| shard.to_vec()));
+    }
+
+    let data_damaged = shards[..layout.data_shards].iter().any(Option::is_none);
+    if !data_damaged && data.is_some_and(|d| d.len() == layout.data_len) {
+        return Ok(Reconstruction::Intact);
+    }
+    if shards.iter().filter(|s| s.is_none()).count() > layout.parity_shards {
+        return Ok(Reconstruction::Unrecoverable);
+    }
+
+    let rs = ReedSolomon::new(layout.data_shards, layout.parity_shards)
+        .map_err(|e| anyhow::anyhow!("invalid parity layout: {e:?}"))?;
+    if rs.reconstruct_data(&mut shards).is_err() {
+        return Ok(Reconstruction::Unrecoverable);
+    }
+
+    let mut repaired = Vec::with_capacity(layout.data_shards * layout.shard_size);
+    for shard in shards.into_iter().take(layout.data_shards).flatten() {
+        repaired.extend_from_slice(&shard);
+    }
+    repaired.truncate(layout.data_len);
+    Ok(Reconstruction::Repaired(repaired))
+}
+
+// ─── Repository Operations ──────────────────────────────────────────────────
+
+/// Writes the parity file for a stored blob.
+pub fn store_parity(repo: &dyn Backend, hash: &str, data: &[u8], redundancy: u8) -> Result<()> {
+    let parity = encode(data, redundancy)?;
+    repo.write(&manifest::parity_key(hash), &parity)?;
+    Ok(())
+}
+
+/// Reads the parity file for a blob, if one exists.
+pub fn read_parity(repo: &dyn Backend, hash: &str) -> Result<Option<Vec<u8>>> {
+    read_if_present(repo, &manifest::parity_key(hash))
+}
+
+/// Checks the stored bytes of a blob against its parity without decoding it.
+pub fn inspect(repo: &dyn Backend, hash: &str) -> Result<ParityStatus> {
+    let Some(parity) = read_parity(repo, hash)? else {
+        return Ok(ParityStatus::Absent);
+    };
+    let data = read_if_present(repo, &manifest::blob_key(hash))?;
+    Ok(match reconstruct(data.as_deref(), &parity) {
+        Ok(Reconstruction::Intact) => ParityStatus::Intact,
+        Ok(Reconstruction::Repaired(_)) => ParityStatus::Recoverable,
+        Ok(Reconstruction::Unrecoverable) => ParityStatus::Unrecoverable,
+        Err(_) => ParityStatus::Absent,
+    })
+}
+
+/// Rebuilds a damaged blob from its parity and writes it back to the store.
+///
+/// Returns true if the blob was rewritten.
+pub fn restore_blob(repo: &dyn Backend, hash: &str) -> Result<bool> {
+    let Some(parity) = read_parity(repo, hash)? else {
+        return Ok(false);
+    };
+    let data = read_if_present(repo, &manifest::blob_key(hash))?;
+    match reconstruct(data.as_deref(), &parity)? {
+        Reconstruction::Repaired(fixed) => {
+            manifest::store_blob(repo, hash, &fixed)?;
+            Ok(true)
+        }
+        Reconstruction::Intact | Reconstruction::Unrecoverable => Ok(false),
+    }
+}
+
+// ─── Helpers ────────────────────────────────────────────────────────────────
+
+/// Reads an object, treating only a missing one as absent: any other error
+/// (e.g. an unreachable remote) must not pass for missing data.
+fn read_if_present(repo: &dyn Backend, key: &str) -> Result<Option<Vec<u8>>> {
+    match repo.read(key) {
+        Ok(data) => Ok(Some(data)),
+        Err(BackendError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn parse_header(parity: &[u8]) -> Result<Layout> {
+    if parity.len() < HEADER_LEN + HASH_LEN || !parity.starts_with(MAGIC) {
+        return Err(anyhow::anyhow!("not a parity file").into());
+    }
+    if blake3::hash(&parity[..HEADER_LEN]).as_bytes() != &parity[HEADER_LEN..][..HASH_LEN] {
+        return Err(anyhow::anyhow!("parity header is corrupted").into());
+    }
+
+    let read_u64 = |at: usize| u64::from_le_bytes(parity[at..at + 8].try_into().unwrap());
+    let read_u32 = |at: usize| u32::from_le_bytes(parity[at..at + 4].try_into().unwrap());
+    let read_u16 = |at: usize| u16::from_le_bytes(parity[at..at + 2].try_into().unwrap());
+
+    let layout = Layout {
+        data_len: read_u64(8) as usize,
+        shard_size: read_u32(16) as usize,
+        data_shards: read_u16(20) as usize,
+        parity_shards: read_u16(22) as usize,
+    };
+
+    let expected_len = HEADER_LEN
+        + HASH_LEN * (1 + layout.total_shards())
+        + layout.parity_shards * layout.shard_size;
+    if layout.data_shards == 0 || layout.parity_shards == 0 || parity.len() != expected_len {
+        return Err(anyhow::anyhow!("parity file is truncated").into());
+    }
+    Ok(layout)
+}
+
+/// Splits data of at most `layout.data_len` bytes into zero-padded shards.
+fn split_shards(data: &[u8], layout: &Layout) -> Vec<Vec<u8>> {
+    (0..layout.data_shards)
+        .map(|i| {
+            let mut shard = vec![0u8; layout.shard_size];
+            let start = (i * layout.shard_size).min(data.len());
+            let end = ((i + 1) * layout.shard_size).min(data.len());
+            shard[..end - start].copy_from_slice(&data[start..end]);
+            shard
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn intact_data_needs_no_repair() {
+        let data = sample(50_000);
+        let parity = encode(&data, 10).unwrap();
+        assert_eq!(
+            reconstruct(Some(&data), &parity).unwrap(),
+            Reconstruction::Intact
+        );
+    }
+
+    #[test]
+    fn repairs_flipped_bytes() {
+        let data = sample(50_000);
+        let parity = encode(&data, 10).unwrap();
+
+        let mut damaged = data.clone();
+        damaged[10] ^= 0xff;
+        damaged[30_000] ^= 0x01;
+
+        match reconstruct(Some(&damaged), &parity).unwrap() {
+            Reconstruction::Repaired(fixed) => assert_eq!(fixed, data),
+            other => panic!("expected repair, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn too_much_damage_is_unrecoverable() {
+        let data = sample(50_000);
+        let parity = encode(&data, 10).unwrap();
+
+        let mut damaged = data.clone();
+        for byte in damaged.iter_mut().step_by(4096) {
+            *byte ^= 0xff;
+        }
+        assert_eq!(
+            reconstruct(Some(&damaged), &parity).unwrap(),
+            Reconstruction::Unrecoverable
+        );
+    }
+
+    #[test]
+    fn full_redundancy_rebuilds_missing_blob() {
+        let data = sample(9_000);
+        let parity = encode(&data, 100).unwrap();
+        match reconstruct(None, &parity).unwrap() {
+            Reconstruction::Repaired(fixed) => assert_eq!(fixed, data),
+            other => panic!("expected repair, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn repairs_truncated_blob() {
+        let data = sample(50_000);
+        let parity = encode(&data, 20).unwrap();
+        match reconstruct(Some(&data[..49_000]), &parity).unwrap() {
+            Reconstruction::Repaired(fixed) => assert_eq!(fixed, data),
+            other => panic!("expected repair, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_header() {
+        let mut parity = encode(&sample(100), 10).unwrap();
+        parity[9] ^= 0xff;
+        assert!(reconstruct(Some(&sample(100)), &parity).is_err());
+    }
+
+    /// A repository whose every request fails, as an unreachable remote does.
+    struct Unreachable;
+
+    impl Backend for Unreachable {
+        fn location(&self) -> String {
+            "unreachable".to_string()
+        }
+        fn list(&self, _: &str) -> crate::backend::BackendResult<Vec<crate::backend::ObjectInfo>> {
+            Err(down())
+        }
+        fn read(&self, _: &str) -> crate::backend::BackendResult<Vec<u8>> {
+            Err(down())
+        }
+        fn read_range(&self, _: &str, _: u64, _: u64) -> crate::backend::BackendResult<Vec<u8>> {
+            Err(down())
+        }
+        fn write(&self, _: &str, _: &[u8]) -> crate::backend::BackendResult<()> {
+            Err(down())
+        }
+        fn delete(&self, _: &str) -> crate::backend::BackendResult<()> {
+            Err(down())
+        }
+        fn exists(&self, _: &str) -> crate::backend::BackendResult<bool> {
+            Err(down())
+        }
+        fn size(&self, _: &str) -> crate::backend::BackendResult<Option<u64>> {
+            Err(down())
+        }
+    }
+
+    fn down() -> BackendError {
+        BackendError::Remote {
+            message: "connection refused".to_string(),
+        }
+    }
+
+    #[test]
+    fn backend_errors_are_not_taken_for_missing_data() {
+        let hash = "ab".repeat(32);
+        assert!(read_parity(&Unreachable, &hash).is_err());
+        assert!(inspect(&Unreachable, &hash).is_err());
+        assert!(manifest::blob_exists(&Unreachable, &hash).is_err());
+        let error = manifest::read_blob(&Unreachable, &hash).unwrap_err();
+        assert!(!manifest::is_missing(&error));
+
+        let dir = std::env::temp_dir().join("but-next-test-parity-missing");
+        let repo = crate::backend::LocalBackend::new(dir);
+        assert!(read_parity(&repo, &hash).unwrap().is_none());
+        assert_eq!(inspect(&repo, &hash).unwrap(), ParityStatus::Absent);
+        assert!(manifest::is_missing(
+            &manifest::read_blob(&repo, &hash).unwrap_err()
+        ));
+    }
+}
@@ -7,8 +7,8 @@
 //! Integrity verification is performed after each file is restored by re-hashing
 //! the written content and comparing against the manifest.
 
+use crate::backend::Backend;
 use crate::compress;
-use crate::config::Settings;
 use crate::crypto;
 use crate::error::{RestoreError, Result};
 use crate::hasher;
@@ -18,8 +18,9 @@ use std::path::PathBuf;
 
 /// Options controlling restore behavior.
 pub struct RestoreOptions<'a> {
-    /// Target directory to restore files into.
-    pub target_dir: PathBuf,
+    /// Target directory to restore files into. Without one, files go back
+    /// to where they were backed up from.
+    pub target_dir: Option<PathBuf>,
 
     /// Optional password for decrypting encrypted snapshots.
     pub password: Option<&'a str>,
@@ -39,25 +40,35 @@ pub struct RestoreOptions<'a> {
 
 /// Restores all files from a snapshot to the target directory.
 pub fn restore_snapshot(
-    settings: &Settings,
+    repo: &dyn Backend,
     snapshot: &Snapshot,
     opts: &RestoreOptions,
 ) -> Result<RestoreStats> {
-    let repo_path = &settings.repo_path;
-
-    // Check target directory
-    if opts.target_dir.exists() && !opts.force {
-        let is_empty = opts
-            .target_dir
+    // Paths in a snapshot of several sources include each source's path, so
+    // they land in their own subdirectories of the target directory, or at
+    // their original locations relative to the filesystem root
+    let target_dir = opts
+        .target_dir
+        .clone()
+        .unwrap_or_else(|| snapshot.source_path.clone());
+    let occupied = if opts.target_dir.is_none() && !snapshot.sources.is_empty() {
+        snapshot.sources.clone()
+    } else {
+        vec![target_dir.clone()]
+    };
+
+    // Check target directories
+    for dir in occupied.iter().filter(|dir| dir.exists()) {
+        let is_empty = dir
             .read_dir()
             .map(|mut d| d.next().is_none())
             .unwrap_or(false);
-        if !is_empty {
-            return Err(RestoreError::TargetExists(opts.target_dir.clone()).into());
+        if !is_empty && !opts.force {
+            return Err(RestoreError::TargetExists(dir.clone()).into());
         }
     }
 
-    std::fs::create_dir_all(&opts.target_dir)?;
+    std::fs::create_dir_all(&target_dir)?;
 
     // Filter files if a filter is specified
     let files: Vec<_> = snapshot
@@ -80,9 +91,16 @@ pub fn restore_snapshot(
     for (relative_path, entry) in &files {
         pb.set_message(crate::backup::format_size(entry.size));
 
+        // Entries marked damaged by `repair` have no usable blob
+        if entry.damaged {
+            stats.files_damaged += 1;
+            pb.inc(1);
+            continue;
+        }
+
         // Read the blob from the store
         let raw_blob =
-            manifest::read_blob(repo_path, &entry.hash).map_err(|_| RestoreError::BlobMissing {
+            manifest::read_blob(repo, &entry.hash).map_err(|_| RestoreError::BlobMissing {
                 hash: entry.hash.clone(),
             })?;
 
@@ -119,7 +137,7 @@ pub fn restore_snapshot(
         }
 
         // Write the file
-        let target_path = opts.target_dir.join(relative_path);
+        let target_path = target_dir.join(relative_path);
 
         if let Some(parent) = target_path.parent() {
             std::fs::create_dir_all(parent)?;
@@ -260,6 +278,7 @@ impl SnapshotDiff {
 pub struct RestoreStats {
     pub files_restored: u64,
     pub bytes_restored: u64,
+    pub files_damaged: u64,
 }
 
 fn create_restore_progress(total: u64) -> ProgressBar {
@@ -0,0 +1,478 @@
+//! # Retention Policies
+//!
+//! Decides which snapshots of a target survive a prune, using
+//! grandfather-father-son rules:
+//!
+//! | Rule           | Keeps                                                     |
+//! |----------------|-----------------------------------------------------------|
+//! | `keep_last`    | the N most recent snapshots                               |
+//! | `keep_hourly`  | the newest snapshot of each of the last N hours that have one |
+//! | `keep_daily`   | ... of each of the last N days                            |
+//! | `keep_weekly`  | ... of each of the last N ISO weeks                       |
+//! | `keep_monthly` | ... of each of the last N months                          |
+//! | `keep_yearly`  | ... of each of the last N years                           |
+//! | `keep_within`  | everything newer than a duration before the newest snapshot |
+//!
+//! A snapshot is kept if any rule selects it. `max_snapshots` then caps the
+//! kept set to the newest N. A policy without rules keeps everything, and
+//! pinned snapshots are always kept without counting towards any rule.
+
+use crate::manifest::Snapshot;
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, Months, NaiveDate, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Retention rules for a target, set in `[settings.retention]` and
+/// overridden per rule in `[backup.<name>.retention]`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_last: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_hourly: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_daily: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_weekly: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_monthly: Option<usize>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_yearly: Option<usize>,
+
+    /// Duration such as `36h`, `14d`, `2w` or `1y6m`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_within: Option<String>,
+}
+
+impl RetentionPolicy {
+    /// Returns true if no rule is set.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Combines two policies; rules set in `self` win over those in `base`.
+    pub fn overlay(&self, base: &Self) -> Self {
+        Self {
+            keep_last: self.keep_last.or(base.keep_last),
+            keep_hourly: self.keep_hourly.or(base.keep_hourly),
+            keep_daily: self.keep_daily.or(base.keep_daily),
+            keep_weekly: self.keep_weekly.or(base.keep_weekly),
+            keep_monthly: self.keep_monthly.or(base.keep_monthly),
+            keep_yearly: self.keep_yearly.or(base.keep_yearly),
+            keep_within: self
+                .keep_within
+                .clone()
+                .or_else(|| base.keep_within.clone()),
+        }
+    }
+}
+
+/// Why a snapshot is kept or removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    Last,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    Within(String),
+    /// Pinned with `but-next pin`, optionally until a date.
+    Pinned(Option<DateTime<Utc>>),
+    /// Named explicitly with `prune --snapshot`.
+    Selected,
+    /// Holds the files of an unchanged snapshot that is kept.
+    Referenced,
+    /// No retention rules apply, so nothing is removed.
+    NoPolicy,
+    /// Selected by no rule.
+    Unmatched,
+    /// Selected, but beyond the `max_snapshots` cap.
+    OverLimit(usize),
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Last => write!(f, "last"),
+            Self::Hourly => write!(f, "hourly"),
+            Self::Daily => write!(f, "daily"),
+            Self::Weekly => write!(f, "weekly"),
+            Self::Monthly => write!(f, "monthly"),
+            Self::Yearly => write!(f, "yearly"),
+            Self::Within(d) => write!(f, "within {d}"),
+            Self::Pinned(None) => write!(f, "pinned"),
+            Self::Pinned(Some(until)) => write!(
+                f,
+                "pinned until {}",
+                until.with_timezone(&Local).format("%Y-%m-%d")
+            ),
+            Self::Selected => write!(f, "selected"),
+            Self::Referenced => write!(f, "files of a kept unchanged snapshot"),
+            Self::NoPolicy => write!(f, "no retention policy"),
+            Self::Unmatched => write!(f, "matched no rule"),
+            Self::OverLimit(max) => write!(f, "over max_snapshots = {max}"),
+        }
+    }
+}
+
+/// Maps a snapshot time to its hour/day/week/... bucket. Buckets follow the
+/// wall clock of the host that took the snapshot, so the outcome doesn't
+/// depend on the timezone `prune` runs in.
+type BucketFn = fn(&DateTime<FixedOffset>) -> i64;
+
+/// The verdict for one snapshot.
+#[derive(Debug, Clone)]
+pub struct Decision<'a> {
+    pub snapshot: &'a Snapshot,
+    pub keep: bool,
+    pub reasons: Vec<Reason>,
+}
+
+/// Applies `policy` (capped at `max_snapshots`, 0 = no cap) to the snapshots
+/// of one target. Decisions are returned newest first.
+pub fn evaluate<'a>(
+    snapshots: &'a [Snapshot],
+    policy: &RetentionPolicy,
+    max_snapshots: usize,
+) -> anyhow::Result<Vec<Decision<'a>>> {
+    let mut sorted: Vec<&Snapshot> = snapshots.iter().collect();
+    sorted.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+
+    let (pinned, sorted): (Vec<&Snapshot>, Vec<&Snapshot>) =
+        sorted.into_iter().partition(|s| s.is_pinned());
+
+    let mut decisions: Vec<Decision> = sorted
+        .iter()
+        .map(|snapshot| Decision {
+            snapshot,
+            keep: false,
+            reasons: Vec::new(),
+        })
+        .collect();
+
+    if policy.is_empty() {
+        for decision in &mut decisions {
+            decision.keep = true;
+            decision.reasons.push(Reason::NoPolicy);
+        }
+    } else {
+        if let Some(n) = policy.keep_last {
+            for decision in decisions.iter_mut().take(n) {
+                decision.keep = true;
+                decision.reasons.push(Reason::Last);
+            }
+        }
+
+        let buckets: [(Option<usize>, Reason, BucketFn); 5] = [
+            (policy.keep_hourly, Reason::Hourly, |t| {
+                i64::from(t.num_days_from_ce()) * 24 + i64::from(t.hour())
+            }),
+            (policy.keep_daily, Reason::Daily, |t| {
+                i64::from(t.num_days_from_ce())
+            }),
+            (policy.keep_weekly, Reason::Weekly, |t| {
+                let week = t.iso_week();
+                i64::from(week.year()) * 100 + i64::from(week.week())
+            }),
+            (policy.keep_monthly, Reason::Monthly, |t| {
+                i64::from(t.year()) * 100 + i64::from(t.month())
+            }),
+            (policy.keep_yearly, Reason::Yearly, |t| i64::from(t.year())),
+        ];
+        for (count, reason, bucket_of) in buckets {
+            let Some(mut remaining) = count else {
+                continue;
+            };
+            let mut last_bucket = None;
+            for decision in &mut decisions {
+                if remaining == 0 {
+                    break;
+                }
+                let bucket = bucket_of(&decision.snapshot.created_at_origin());
+                if last_bucket != Some(bucket) {
+                    last_bucket = Some(bucket);
+                    decision.keep = true;
+                    decision.reasons.push(reason.clone());
+                    remaining -= 1;
+                }
+            }
+        }
+
+        if let (Some(within), Some(newest)) = (&policy.keep_within, sorted.first()) {
+            let cutoff = subtract(newest.created_at, within)?;
+            for decision in &mut decisions {
+                if decision.snapshot.created_at >= cutoff {
+                    decision.keep = true;
+                    decision.reasons.push(Reason::Within(within.clone()));
+                }
+            }
+        }
+
+        for decision in &mut decisions {
+            if !decision.keep {
+                decision.reasons.push(Reason::Unmatched);
+            }
+        }
+    }
+
+    if max_snapshots > 0 {
+        for decision in decisions.iter_mut().filter(|d| d.keep).skip(max_snapshots) {
+            decision.keep = false;
+            decision.reasons.push(Reason::OverLimit(max_snapshots));
+        }
+    }
+
+    decisions.extend(pinned.into_iter().map(|snapshot| Decision {
+        snapshot,
+        keep: true,
+        reasons: vec![Reason::Pinned(snapshot.pinned_until)],
+    }));
+    decisions.sort_by_key(|d| std::cmp::Reverse(d.snapshot.created_at));
+
+    Ok(decisions)
+}
+
+/// Parses a duration like `1y6m`, `2w`, `36h` into (months, remaining time).
+pub fn parse_duration(input: &str) -> anyhow::Result<(u32, Duration)> {
+    let invalid =
+        || anyhow::anyhow!("invalid duration '{input}' (expected e.g. 7d, 2w, 1y6m, 36h)");
+    let mut months = 0u32;
+    let mut rest = Duration::zero();
+    let mut digits = String::new();
+
+    for c in input.trim().chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let n: u32 = digits.parse().map_err(|_| invalid())?;
+        digits.clear();
+        let (added_months, added) = match c {
+            'y' => (n.checked_mul(12), None),
+            'm' => (Some(n), None),
+            'w' => (Some(0), Some(Duration::weeks(n.into()))),
+            'd' => (Some(0), Some(Duration::days(n.into()))),
+            'h' => (Some(0), Some(Duration::hours(n.into()))),
+            _ => return Err(invalid()),
+        };
+        months = added_months
+            .and_then(|m| months.checked_add(m))
+            .ok_or_else(|| out_of_range(input))?;
+        if let Some(added) = added {
+            rest = rest
+                .checked_add(&added)
+                .ok_or_else(|| out_of_range(input))?;
+        }
+    }
+    if !digits.is_empty() || (months == 0 && rest.is_zero()) {
+        return Err(invalid());
+    }
+    Ok((months, rest))
+}
+
+/// Parses a pin expiry: a date (`2025-12-31`) or a duration from `now` (`90d`).
+/// Dates are taken as the end of that day in local time.
+pub fn parse_expiry(input: &str, now: DateTime<Local>) -> anyhow::Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        return date
+            .and_hms_opt(23, 59, 59)
+            .and_then(|t| t.and_local_timezone(Local).earliest())
+            .map(|t| t.with_timezone(&Utc))
+            .ok_or_else(|| anyhow::anyhow!("invalid date '{input}'"));
+    }
+    let (months, rest) = parse_duration(input).map_err(|_| {
+        anyhow::anyhow!("invalid expiry '{input}' (expected YYYY-MM-DD or e.g. 90d)")
+    })?;
+    let time = now
+        .checked_add_months(Months::new(months))
+        .and_then(|t| t.checked_add_signed(rest))
+        .ok_or_else(|| out_of_range(input))?;
+    Ok(time.with_timezone(&Utc))
+}
+
+/// `time` moved back by a duration such as `1y6m` (see [`parse_duration`]).
+pub fn subtract(time: DateTime<Utc>, duration: &str) -> anyhow::Result<DateTime<Utc>> {
+    let (months, rest) = parse_duration(duration)?;
+    time.checked_sub_months(Months::new(months))
+        .and_then(|t| t.checked_sub_signed(rest))
+        .ok_or_else(|| out_of_range(duration))
+}
+
+fn out_of_range(duration: &str) -> anyhow::Error {
+    anyhow::anyhow!("duration '{duration}' is out of range")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CompressionKind;
+    use chrono::TimeZone;
+    use std::path::PathBuf;
+
+    fn snapshot_at(y: i32, m: u32, d: u32, h: u32) -> Snapshot {
+        let mut snap = Snapshot::new("t", PathBuf::from("/"), CompressionKind::None, false);
+        let local = Local.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap();
+        snap.created_at = local.with_timezone(&Utc);
+        snap.utc_offset = Some(local.offset().local_minus_utc());
+        snap.id = local.format("%Y%m%d-%H").to_string();
+        snap
+    }
+
+    fn kept(decisions: &[Decision]) -> Vec<String> {
+        decisions
+            .iter()
+            .filter(|d| d.keep)
+            .map(|d| d.snapshot.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn daily_keeps_newest_per_day() {
+        let snaps = vec![
+            snapshot_at(2024, 5, 1, 9),
+            snapshot_at(2024, 5, 1, 18),
+            snapshot_at(2024, 5, 2, 9),
+            snapshot_at(2024, 5, 3, 9),
+            snapshot_at(2024, 5, 3, 12),
+        ];
+        let policy = RetentionPolicy {
+            keep_daily: Some(2),
+            ..Default::default()
+        };
+        let decisions = evaluate(&snaps, &policy, 0).unwrap();
+        assert_eq!(kept(&decisions), vec!["20240503-12", "20240502-09"]);
+        assert_eq!(decisions[0].reasons, vec![Reason::Daily]);
+        assert_eq!(decisions[4].reasons, vec![Reason::Unmatched]);
+    }
+
+    #[test]
+    fn rules_combine_and_cap_applies() {
+        let snaps: Vec<_> = (1..=12)
+            .map(|m| snapshot_at(2023, m, 15, 12))
+            .chain([snapshot_at(2022, 6, 1, 12), snapshot_at(2021, 6, 1, 12)])
+            .collect();
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            keep_monthly: Some(3),
+            keep_yearly: Some(3),
+            ..Default::default()
+        };
+        let decisions = evaluate(&snaps, &policy, 0).unwrap();
+        assert_eq!(
+            kept(&decisions),
+            vec![
+                "20231215-12",
+                "20231115-12",
+                "20231015-12",
+                "20220601-12",
+                "20210601-12"
+            ]
+        );
+        assert_eq!(
+            decisions[0].reasons,
+            vec![Reason::Last, Reason::Monthly, Reason::Yearly]
+        );
+
+        let capped = evaluate(&snaps, &policy, 2).unwrap();
+        assert_eq!(kept(&capped), vec!["20231215-12", "20231115-12"]);
+        assert!(capped[2].reasons.contains(&Reason::OverLimit(2)));
+    }
+
+    #[test]
+    fn keep_within_is_relative_to_newest() {
+        let snaps = vec![
+            snapshot_at(2024, 1, 1, 0),
+            snapshot_at(2024, 1, 20, 0),
+            snapshot_at(2024, 2, 1, 0),
+        ];
+        let policy = RetentionPolicy {
+            keep_within: Some("2w".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            kept(&evaluate(&snaps, &policy, 0).unwrap()),
+            vec!["20240201-00", "20240120-00"]
+        );
+    }
+
+    #[test]
+    fn empty_policy_keeps_everything() {
+        let snaps = vec![snapshot_at(2024, 1, 1, 0), snapshot_at(2024, 1, 2, 0)];
+        let decisions = evaluate(&snaps, &RetentionPolicy::default(), 0).unwrap();
+        assert_eq!(kept(&decisions).len(), 2);
+
+        let capped = evaluate(&snaps, &RetentionPolicy::default(), 1).unwrap();
+        assert_eq!(kept(&capped), vec!["20240102-00"]);
+    }
+
+    #[test]
+    fn pinned_snapshots_survive_and_dont_count() {
+        let mut snaps = vec![
+            snapshot_at(2024, 1, 1, 0),
+            snapshot_at(2024, 1, 2, 0),
+            snapshot_at(2024, 1, 3, 0),
+        ];
+        snaps[2].pinned = true;
+        snaps[0].pinned = true;
+        snaps[0].pinned_until = Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..Default::default()
+        };
+        let decisions = evaluate(&snaps, &policy, 1).unwrap();
+        // The expired pin on the oldest snapshot no longer protects it
+        assert_eq!(kept(&decisions), vec!["20240103-00", "20240102-00"]);
+        assert_eq!(decisions[0].reasons, vec![Reason::Pinned(None)]);
+        assert_eq!(decisions[1].reasons, vec![Reason::Last]);
+    }
+
+    #[test]
+    fn duration_parsing() {
+        assert_eq!(parse_duration("1y6m").unwrap(), (18, Duration::zero()));
+        assert_eq!(
+            parse_duration("2w3d12h").unwrap(),
+            (0, Duration::days(17) + Duration::hours(12))
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("7x").is_err());
+        assert!(parse_duration("4000000000y").is_err());
+        assert!(subtract(Utc::now(), "100000000d").is_err());
+        assert!(subtract(Utc::now(), &"4000000000w".repeat(4)).is_err());
+        assert!(parse_expiry("100000000d", Local::now()).is_err());
+
+        let now = Local.with_ymd_and_hms(2024, 1, 31, 12, 0, 0).unwrap();
+        assert_eq!(
+            parse_expiry("1m", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap()
+        );
+        assert_eq!(
+            parse_expiry("2024-06-30", now).unwrap(),
+            Local.with_ymd_and_hms(2024, 6, 30, 23, 59, 59).unwrap()
+        );
+        assert!(parse_expiry("next week", now).is_err());
+    }
+
+    #[test]
+    fn overlay_prefers_target_rules() {
+        let global = RetentionPolicy {
+            keep_daily: Some(7),
+            keep_weekly: Some(4),
+            ..Default::default()
+        };
+        let target = RetentionPolicy {
+            keep_daily: Some(30),
+            ..Default::default()
+        };
+        let merged = target.overlay(&global);
+        assert_eq!(merged.keep_daily, Some(30));
+        assert_eq!(merged.keep_weekly, Some(4));
+    }
+}
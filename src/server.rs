@@ -0,0 +1,606 @@
+//! # REST Server
+//!
+//! Exposes any [`Backend`] over HTTP(S) so one machine can host repositories
+//! for many clients without giving them shell access. Clients use the
+//! matching `rest:` backend ([`crate::backend::rest`]), so every manifest
+//! operation works unchanged over the network.
+//!
+//! ## Protocol
+//!
+//! ```text
+//! GET    /objects?prefix=<p>   list objects → JSON [{"key": "...", "size": n}]
+//! GET    /objects/<key>        read an object (honours `Range: bytes=a-b`)
+//! HEAD   /objects/<key>        existence and size (Content-Length)
+//! PUT    /objects/<key>        write an object
+//! DELETE /objects/<key>        delete an object
+//! ```
+//!
+//! Every request must carry HTTP Basic credentials for a client listed in the
+//! clients file. Append-only clients may add objects but not delete them or
+//! change existing ones, so a compromised laptop cannot destroy its history.
+//!
+//! ## Clients File
+//!
+//! One client per line, managed with `but-next serve --add-client`:
+//!
+//! ```text
+//! # name:password-hash[:append-only]
+//! laptop:$argon2id$v=19$m=19456,t=2,p=1$3q...$Xk...:append-only
+//! ```
+//!
+//! Passwords are stored as argon2id hashes with their parameters, so a
+//! leaked file doesn't give them away cheaply.
+
+use crate::backend::{percent_decode, Backend, BackendResult};
+use crate::error::{BackendError, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+/// Number of worker threads handling requests concurrently.
+const WORKERS: usize = 8;
+
+/// Options for [`serve`].
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Address to listen on, e.g. `0.0.0.0:8000`.
+    pub listen: String,
+
+    /// Treat every client as append-only regardless of the clients file.
+    pub append_only: bool,
+
+    /// Give each client its own repository below `<client>/`.
+    pub private_repos: bool,
+
+    /// PEM certificate and private key; serves HTTPS when set.
+    pub tls: Option<(PathBuf, PathBuf)>,
+
+    /// Largest object a client may upload, in bytes. Uploads are held in
+    /// memory, so this bounds what one request can allocate.
+    pub max_object_size: u64,
+
+    /// Log every request.
+    pub verbose: bool,
+}
+
+/// A client allowed to access the server.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// Password hash as a PHC string (`$argon2id$v=19$m=...$salt$hash`).
+    hash: String,
+    pub append_only: bool,
+    /// Keyed digest of the last password that verified, so that only the
+    /// first request of a client pays for the slow hash.
+    verified: Arc<Mutex<Option<blake3::Hash>>>,
+    cache_key: [u8; 32],
+}
+
+/// Credentials loaded from the clients file, keyed by client name.
+pub type Clients = BTreeMap<String, Client>;
+
+impl Client {
+    fn new(password: &str, append_only: bool) -> Result<Self> {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())
+            .map_err(|e| anyhow::anyhow!("failed to hash password: {e}"))?;
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| anyhow::anyhow!("failed to hash password: {e}"))?;
+        Ok(Self::with_hash(hash.to_string(), append_only))
+    }
+
+    fn with_hash(hash: String, append_only: bool) -> Self {
+        Self {
+            hash,
+            append_only,
+            verified: Arc::default(),
+            cache_key: rand::random(),
+        }
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        // blake3::Hash compares in constant time
+        let digest = blake3::keyed_hash(&self.cache_key, password.as_bytes());
+        let mut verified = self.verified.lock().unwrap_or_else(|e| e.into_inner());
+        if *verified == Some(digest) {
+            return true;
+        }
+        let ok = PasswordHash::new(&self.hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        });
+        if ok {
+            *verified = Some(digest);
+        }
+        ok
+    }
+
+    fn to_line(&self, name: &str) -> String {
+        let mut line = format!("{name}:{}", self.hash);
+        if self.append_only {
+            line.push_str(":append-only");
+        }
+        line
+    }
+
+    fn parse_line(line: &str) -> Option<(String, Self)> {
+        let mut fields = line.split(':');
+        let name = fields.next().filter(|n| valid_client_name(n))?;
+        let hash = fields.next().filter(|h| PasswordHash::new(h).is_ok())?;
+        let append_only = match fields.next() {
+            None => false,
+            Some("append-only") => true,
+            Some(_) => return None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        Some((
+            name.to_string(),
+            Self::with_hash(hash.to_string(), append_only),
+        ))
+    }
+}
+
+/// Client names double as directory names with `--private-repos`.
+fn valid_client_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && name != "."
+        && name != ".."
+}
+
+/// Loads the clients file.
+pub fn load_clients(path: &Path) -> Result<Clients> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", path.display()))?;
+
+    let mut clients = Clients::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, client) = Client::parse_line(line).ok_or_else(|| {
+            anyhow::anyhow!("{}:{}: malformed client entry", path.display(), number + 1)
+        })?;
+        clients.insert(name, client);
+    }
+    Ok(clients)
+}
+
+/// Adds a client (or replaces its password) in the clients file.
+pub fn add_client(path: &Path, name: &str, password: &str, append_only: bool) -> Result<()> {
+    if !valid_client_name(name) {
+        return Err(anyhow::anyhow!(
+            "invalid client name '{name}' (use letters, digits, '-', '_' and '.')"
+        )
+        .into());
+    }
+
+    let mut clients = if path.exists() {
+        load_clients(path)?
+    } else {
+        Clients::new()
+    };
+    clients.insert(name.to_string(), Client::new(password, append_only)?);
+
+    let mut content = String::from("# name:password-hash[:append-only]\n");
+    for (name, client) in &clients {
+        content.push_str(&client.to_line(name));
+        content.push('\n');
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+// ─── Server ─────────────────────────────────────────────────────────────────
+
+struct State {
+    repo: Box<dyn Backend>,
+    clients: Clients,
+    options: ServeOptions,
+}
+
+/// Serves `repo` until the process is terminated.
+pub fn serve(repo: Box<dyn Backend>, clients: Clients, options: ServeOptions) -> Result<()> {
+    if clients.is_empty() {
+        return Err(anyhow::anyhow!("no clients configured; add one with --add-client").into());
+    }
+
+    let server = match &options.tls {
+        Some((cert, key)) => {
+            let ssl = tiny_http::SslConfig {
+                certificate: fs::read(cert)
+                    .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", cert.display()))?,
+                private_key: fs::read(key)
+                    .map_err(|e| anyhow::anyhow!("failed to read {}: {e}", key.display()))?,
+            };
+            Server::https(&options.listen, ssl)
+        }
+        None => Server::http(&options.listen),
+    }
+    .map_err(|e| anyhow::anyhow!("failed to listen on {}: {e}", options.listen))?;
+
+    let server = Arc::new(server);
+    let state = Arc::new(State {
+        repo,
+        clients,
+        options,
+    });
+
+    let workers: Vec<_> = (0..WORKERS)
+        .map(|_| {
+            let server = Arc::clone(&server);
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || {
+                while let Ok(request) = server.recv() {
+                    handle(&state, request);
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+fn handle(state: &State, mut request: Request) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let (client, response) = match authenticate(state, &request) {
+        Some((name, client)) => {
+            let response = route(state, name, client, &mut request).unwrap_or_else(|e| {
+                let status = match e {
+                    BackendError::NotFound(_) => 404,
+                    _ => 500,
+                };
+                text(status, &e.to_string())
+            });
+            (name.as_str(), response)
+        }
+        None => (
+            "-",
+            text(401, "authentication required")
+                .with_header(header("WWW-Authenticate", "Basic realm=\"but-next\"")),
+        ),
+    };
+
+    if state.options.verbose {
+        eprintln!(
+            "  {} {client} {method} {url} → {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            response.status_code().0,
+        );
+    }
+    let _ = request.respond(response);
+}
+
+fn authenticate<'a>(state: &'a State, request: &Request) -> Option<(&'a String, &'a Client)> {
+    use base64::Engine;
+
+    let value = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))?
+        .value
+        .as_str();
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let (name, password) = std::str::from_utf8(&decoded).ok()?.split_once(':')?;
+    let (name, client) = state.clients.get_key_value(name)?;
+    client.verify(password).then_some((name, client))
+}
+
+fn route(
+    state: &State,
+    name: &str,
+    client: &Client,
+    request: &mut Request,
+) -> BackendResult<HttpResponse> {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let namespace = if state.options.private_repos {
+        format!("{name}/")
+    } else {
+        String::new()
+    };
+    let append_only = state.options.append_only || client.append_only;
+    let repo = state.repo.as_ref();
+
+    if path == "/objects" {
+        if *request.method() != Method::Get {
+            return Ok(text(405, "method not allowed"));
+        }
+        let prefix = match query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("prefix="))
+        {
+            Some(encoded) => percent_decode(encoded).filter(|p| valid_prefix(p)),
+            None => Some(String::new()),
+        };
+        let Some(prefix) = prefix else {
+            return Ok(text(400, "invalid prefix"));
+        };
+        let mut objects = repo.list(&format!("{namespace}{prefix}"))?;
+        for object in &mut objects {
+            object.key.drain(..namespace.len());
+        }
+        let json = serde_json::to_vec(&objects).map_err(|e| BackendError::Remote {
+            message: e.to_string(),
+        })?;
+        return Ok(
+            Response::from_data(json).with_header(header("Content-Type", "application/json"))
+        );
+    }
+
+    let Some(key) = path
+        .strip_prefix("/objects/")
+        .and_then(percent_decode)
+        .filter(|k| valid_key(k))
+    else {
+        return Ok(text(404, "not found"));
+    };
+    let full_key = format!("{namespace}{key}");
+
+    match request.method() {
+        Method::Get => match range(request) {
+            Some((offset, length)) => {
+                let data = repo.read_range(&full_key, offset, length)?;
+                Ok(Response::from_data(data).with_status_code(206))
+            }
+            None => Ok(Response::from_data(repo.read(&full_key)?)),
+        },
+        Method::Head => match repo.size(&full_key)? {
+            Some(size) => Ok(Response::new(
+                StatusCode(200),
+                Vec::new(),
+                Cursor::new(Vec::new()),
+                Some(size as usize),
+                None,
+            )),
+            None => Ok(text(404, "not found")),
+        },
+        Method::Put => {
+            let limit = state.options.max_object_size;
+            let too_large = || text(413, &format!("objects are limited to {limit} bytes"));
+            if request.body_length().is_some_and(|len| len as u64 > limit) {
+                return Ok(too_large());
+            }
+            let mut data = Vec::new();
+            request
+                .as_reader()
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut data)
+                .map_err(|e| BackendError::Io {
+                    key: key.clone(),
+                    source: e,
+                })?;
+            if data.len() as u64 > limit {
+                return Ok(too_large());
+            }
+            // Re-uploading identical content is harmless and happens when a
+            // backup is retried, so only real modifications are refused.
+            // Blob keys are content hashes, so an existing blob already holds
+            // this content.
+            if append_only && repo.exists(&full_key)? {
+                if key.starts_with("blobs/") {
+                    return Ok(text(204, ""));
+                }
+                if repo.read(&full_key)? != data {
+                    return Ok(text(
+                        403,
+                        "append-only: existing objects cannot be modified",
+                    ));
+                }
+            }
+            repo.write(&full_key, &data)?;
+            Ok(text(204, ""))
+        }
+        Method::Delete => {
+            if append_only {
+                return Ok(text(403, "append-only: objects cannot be deleted"));
+            }
+            repo.delete(&full_key)?;
+            Ok(text(204, ""))
+        }
+        _ => Ok(text(405, "method not allowed")),
+    }
+}
+
+/// Parses `Range: bytes=a-b` / `bytes=a-` into `(offset, length)`.
+fn range(request: &Request) -> Option<(u64, u64)> {
+    let value = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Range"))?
+        .value
+        .as_str();
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let start: u64 = start.parse().ok()?;
+    let length = match end {
+        "" => u64::MAX - start,
+        end => end.parse::<u64>().ok()?.checked_sub(start)? + 1,
+    };
+    Some((start, length))
+}
+
+/// Rejects keys that could escape the repository root.
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.contains('\\')
+        && key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+}
+
+/// Accepts an empty listing prefix, or one that is a valid key once a
+/// trailing `/` is removed.
+fn valid_prefix(prefix: &str) -> bool {
+    prefix.is_empty() || valid_key(prefix.strip_suffix('/').unwrap_or(prefix))
+}
+
+fn text(status: u16, body: &str) -> HttpResponse {
+    Response::from_string(body).with_status_code(status)
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("static header is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::rest::RestBackend;
+    use crate::backend::LocalBackend;
+
+    fn start(name: &str, options: ServeOptions, clients: Clients) -> (PathBuf, String) {
+        let dir = std::env::temp_dir().join(format!("but-next-test-serve-{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        let repo = Box::new(LocalBackend::new(dir.clone()));
+
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let state = Arc::new(State {
+            repo,
+            clients,
+            options,
+        });
+        std::thread::spawn(move || {
+            while let Ok(request) = server.recv() {
+                handle(&state, request);
+            }
+        });
+        (dir, format!("http://{addr}"))
+    }
+
+    fn options() -> ServeOptions {
+        ServeOptions {
+            listen: String::new(),
+            append_only: false,
+            private_repos: false,
+            tls: None,
+            max_object_size: 1 << 20,
+            verbose: false,
+        }
+    }
+
+    #[test]
+    fn clients_file_roundtrip() {
+        let path = std::env::temp_dir().join("but-next-test-clients");
+        let _ = fs::remove_file(&path);
+        add_client(&path, "laptop", "secret", true).unwrap();
+        add_client(&path, "desktop", "hunter2", false).unwrap();
+
+        let clients = load_clients(&path).unwrap();
+        assert!(clients["laptop"].verify("secret"));
+        assert!(!clients["laptop"].verify("Secret"));
+        assert!(clients["laptop"].verify("secret"));
+        assert!(clients["laptop"].append_only);
+        assert!(!clients["desktop"].verify("secret"));
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("laptop:$argon2id$"));
+        assert!(!content.contains("secret"));
+        assert!(Client::parse_line("laptop:5f1c:9a0e").is_none());
+        assert!(!clients["desktop"].append_only);
+        assert!(add_client(&path, "../evil", "x", false).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rest_backend_roundtrip() {
+        let clients = Clients::from([("laptop".to_string(), Client::new("pw", false).unwrap())]);
+        let (dir, url) = start("roundtrip", options(), clients);
+        let url = url.replace("http://", "http://laptop:pw@");
+        let rest = RestBackend::open(&url).unwrap();
+
+        rest.write("snapshots/a b.json", b"{}").unwrap();
+        rest.write("blobs/ab/cdef", b"0123456789").unwrap();
+        assert_eq!(rest.read("snapshots/a b.json").unwrap(), b"{}");
+        assert_eq!(rest.read_range("blobs/ab/cdef", 2, 3).unwrap(), b"234");
+        assert_eq!(rest.size("blobs/ab/cdef").unwrap(), Some(10));
+        assert!(!rest.exists("blobs/ab/0000").unwrap());
+        assert!(matches!(
+            rest.read("blobs/ab/0000"),
+            Err(BackendError::NotFound(_))
+        ));
+
+        let listed = rest.list("blobs/").unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].key, "blobs/ab/cdef");
+
+        rest.delete("blobs/ab/cdef").unwrap();
+        assert!(rest.list("blobs/").unwrap().is_empty());
+        assert!(rest.write("blobs/ab/big", &vec![0; (1 << 20) + 1]).is_err());
+        assert!(!dir.join("blobs/ab/big").exists());
+        assert!(dir.join("snapshots/a b.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_credentials() {
+        let clients = Clients::from([("laptop".to_string(), Client::new("pw", false).unwrap())]);
+        let (dir, url) = start("auth", options(), clients);
+        let rest = RestBackend::open(&url.replace("http://", "http://laptop:wrong@")).unwrap();
+        assert!(matches!(rest.list(""), Err(BackendError::Remote { .. })));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn append_only_and_private_repos() {
+        let clients = Clients::from([("laptop".to_string(), Client::new("pw", true).unwrap())]);
+        let opts = ServeOptions {
+            private_repos: true,
+            ..options()
+        };
+        let (dir, url) = start("append", opts, clients);
+        let rest = RestBackend::open(&url.replace("http://", "http://laptop:pw@")).unwrap();
+
+        rest.write("snapshots/s.json", b"v1").unwrap();
+        rest.write("snapshots/s.json", b"v1").unwrap();
+        assert!(rest.write("snapshots/s.json", b"v2").is_err());
+        assert!(rest.delete("snapshots/s.json").is_err());
+        assert_eq!(rest.read("snapshots/s.json").unwrap(), b"v1");
+        // Blobs are addressed by content, so a rewrite is accepted unread
+        rest.write("blobs/ab/cdef", b"sealed").unwrap();
+        rest.write("blobs/ab/cdef", b"resealed").unwrap();
+        assert_eq!(rest.read("blobs/ab/cdef").unwrap(), b"sealed");
+        assert_eq!(rest.list("snap").unwrap()[0].key, "snapshots/s.json");
+        assert!(dir.join("laptop/snapshots/s.json").exists());
+        assert!(rest.list("../").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn key_validation() {
+        assert!(valid_key("blobs/ab/cd"));
+        assert!(!valid_key("../etc/passwd"));
+        assert!(!valid_key("blobs//x"));
+        assert!(!valid_key("/abs"));
+        assert!(!valid_key("a\\b"));
+        assert!(valid_prefix(""));
+        assert!(valid_prefix("snapshots/"));
+        assert!(valid_prefix("blobs/ab"));
+        assert!(!valid_prefix("../../"));
+        assert!(!valid_prefix("/etc"));
+        assert!(!valid_prefix("blobs//"));
+        assert_eq!(percent_decode("a%20b%2Fc").unwrap(), "a b/c");
+    }
+}
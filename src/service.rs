@@ -0,0 +1,206 @@
+//! # Long-running Services
+//!
+//! Plumbing shared by `watch` and `daemon`, which run until they are stopped
+//! and so must outlive whatever goes wrong along the way:
+//!
+//! - **Signals** — the first SIGTERM or SIGINT asks for a graceful stop: the
+//!   file being stored is finished, the snapshot in progress is abandoned
+//!   before its manifest is written (the blobs already stored are reused by
+//!   the next backup) and the service's state is saved. A second signal exits
+//!   immediately.
+//! - **Backoff** — a failing target or job is retried after [`Backoff::BASE`],
+//!   doubling with every further failure up to [`Backoff::MAX`], rather than
+//!   ending the service.
+//! - **Config reload** — the configuration file is re-read when it changes on
+//!   disk. An edit that doesn't load is reported and the previous
+//!   configuration stays in effect.
+
+use crate::backend::{self, Backend};
+use crate::config::{self, Config};
+use crate::error::Result;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often blocking waits check for a stop request.
+pub const TICK: Duration = Duration::from_millis(250);
+
+/// Set once SIGTERM or SIGINT was received.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    /// Installs the signal handlers.
+    pub fn install() -> Result<Self> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        let flag = Arc::new(AtomicBool::new(false));
+        for signal in [SIGTERM, SIGINT] {
+            // Registered first, so it only fires on the second signal
+            signal_hook::flag::register_conditional_shutdown(signal, 130, Arc::clone(&flag))
+                .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&flag)))
+                .map_err(|e| anyhow::anyhow!("failed to install signal handler: {e}"))?;
+        }
+        Ok(Self(flag))
+    }
+
+    pub fn requested(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Asks for a graceful stop, as the first signal does.
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// The flag checked by long operations such as a backup.
+    pub fn flag(&self) -> &AtomicBool {
+        &self.0
+    }
+
+    /// Sleeps for `duration`, returning early if a stop is requested.
+    pub fn sleep(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        while !self.requested() {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            std::thread::sleep(left.min(TICK));
+        }
+    }
+}
+
+/// Exponential backoff after consecutive failures.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    failures: u32,
+}
+
+impl Backoff {
+    /// Delay after the first failure.
+    pub const BASE: Duration = Duration::from_secs(30);
+    /// Longest delay, however many failures.
+    pub const MAX: Duration = Duration::from_secs(3600);
+
+    /// Records a failure and returns how long to wait before retrying.
+    pub fn fail(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        let factor = 1u32 << (self.failures - 1).min(16);
+        Self::BASE.saturating_mul(factor).min(Self::MAX)
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// The configuration file of a running service, re-read when it changes.
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigFile {
+    /// Loads the configuration and opens its repository.
+    pub fn open(path: PathBuf) -> Result<(Self, Config, Box<dyn Backend>)> {
+        let modified = modified(&path);
+        let config = config::load_config_from(&path)?;
+        let repo = backend::open(&config.settings)?;
+        Ok((Self { path, modified }, config, repo))
+    }
+
+    /// Returns the new configuration and repository if the file changed since
+    /// it was last read. A file that doesn't load is reported once and then
+    /// ignored until it changes again.
+    pub fn reload(&mut self) -> Option<(Config, Box<dyn Backend>)> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        let result = config::load_config_from(&self.path)
+            .and_then(|config| Ok((backend::open(&config.settings)?, config)));
+        match result {
+            Ok((repo, config)) => {
+                eprintln!(
+                    "\n  {} Reloaded {}",
+                    colored::Colorize::green("✓"),
+                    self.path.display(),
+                );
+                Some((config, repo))
+            }
+            Err(e) => {
+                eprintln!(
+                    "\n  {} Keeping the previous configuration: {e}",
+                    colored::Colorize::yellow("!"),
+                );
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.fail(), Duration::from_secs(30));
+        assert_eq!(backoff.fail(), Duration::from_secs(60));
+        assert_eq!(backoff.fail(), Duration::from_secs(120));
+        for _ in 0..40 {
+            backoff.fail();
+        }
+        assert_eq!(backoff.fail(), Backoff::MAX);
+
+        backoff.reset();
+        assert_eq!(backoff.fail(), Backoff::BASE);
+    }
+
+    #[test]
+    fn config_is_reloaded_only_when_valid() {
+        let dir = std::env::temp_dir().join("but-next-test-service-reload");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let path = dir.join("but-next.toml");
+        let write = |target: &str, mtime: u64| {
+            let toml = format!(
+                "[settings]\nrepo_path = {:?}\n\n[backup.{target}]\nfrom = {:?}\n",
+                dir.join(".but"),
+                dir.join("src"),
+            );
+            std::fs::write(&path, toml).unwrap();
+            // Coarse filesystem timestamps would hide quick successive writes
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(mtime);
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+
+        write("docs", 1_000);
+        let (mut file, config, _) = ConfigFile::open(path.clone()).unwrap();
+        assert!(config.backup.contains_key("docs"));
+        assert!(file.reload().is_none());
+
+        write("photos", 2_000);
+        let (config, _) = file.reload().unwrap();
+        assert!(config.backup.contains_key("photos"));
+
+        std::fs::write(&path, "[backup.broken").unwrap();
+        assert!(file.reload().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
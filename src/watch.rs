@@ -0,0 +1,565 @@
+//! # Watch Mode
+//!
+//! Backs up targets as their sources change. Each target's `from` directory
+//! is watched recursively through the platform's change notifications
+//! (inotify, FSEvents, ReadDirectoryChangesW) and only targets that changed
+//! are backed up. Dynamic targets are watched as the sub-targets their glob
+//! matches; the glob is expanded again every `interval` seconds, so new
+//! directories are picked up and vanished ones dropped.
+//!
+//! Changes are debounced: a target is backed up once it has been quiet for
+//! [`DEBOUNCE`], or at the latest `interval` seconds after its first change,
+//! so a long copy into the source yields one snapshot rather than dozens.
+//!
+//! Targets that can't be watched — typically because the inotify watch limit
+//! (`fs.inotify.max_user_watches`) is exhausted — fall back to a full rescan
+//! every `interval` seconds, as does every target with `--poll`.
+//!
+//! A failed backup leaves the target's changes pending and is retried with
+//! [`Backoff`], the configuration is reloaded when it changes, and SIGTERM or
+//! SIGINT stops after the current file (see [`crate::service`]). While it
+//! runs, `but-next ctl` talks to it through [`crate::control`].
+
+use crate::backup::{self, BackupOptions};
+use crate::config::{BackupTarget, Config};
+use crate::control::{self, LastRun, Shared};
+use crate::error::Result;
+use crate::service::{Backoff, ConfigFile, Shutdown, TICK};
+use chrono::Utc;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Quiet period after the last change before a target is backed up.
+pub const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How a target's source is monitored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Events,
+    Polling,
+}
+
+/// Change tracking for one target.
+#[derive(Debug)]
+struct TargetState {
+    /// Canonical source directories, for matching event paths.
+    roots: Vec<PathBuf>,
+    mode: Mode,
+    /// First and latest change not yet backed up.
+    pending: Option<(Instant, Instant)>,
+    /// Next rescan of a polled target.
+    next_poll: Instant,
+    /// Consecutive failed backups, and when the next attempt may start.
+    backoff: Backoff,
+    retry_at: Option<Instant>,
+    /// Backup requested through the control socket.
+    triggered: bool,
+}
+
+impl TargetState {
+    /// Whether `path` is inside one of the target's sources.
+    fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    fn unwatch(&self, watcher: &mut RecommendedWatcher) {
+        for root in &self.roots {
+            let _ = watcher.unwatch(root);
+        }
+    }
+
+    /// When the target should next be backed up, if at all.
+    fn deadline(&self, interval: Duration) -> Option<Instant> {
+        let due = match self.mode {
+            Mode::Polling => Some(self.next_poll),
+            Mode::Events => self
+                .pending
+                .map(|(first, last)| (last + DEBOUNCE).min(first + interval)),
+        };
+        match self.retry_at {
+            Some(retry_at) => due.map(|due| due.max(retry_at)),
+            None => due,
+        }
+    }
+
+    fn touch(&mut self, now: Instant) {
+        let first = self.pending.map_or(now, |(first, _)| first);
+        self.pending = Some((first, now));
+    }
+
+    /// Records a backup attempt; a failed one keeps the changes pending and
+    /// is retried after a growing delay.
+    fn finish(&mut self, ok: bool, interval: Duration) -> Option<Duration> {
+        let now = Instant::now();
+        self.next_poll = now + interval;
+        self.triggered = false;
+        if ok {
+            self.pending = None;
+            self.backoff.reset();
+            self.retry_at = None;
+            return None;
+        }
+        let delay = self.backoff.fail();
+        self.pending.get_or_insert((now, now));
+        self.retry_at = Some(now + delay);
+        Some(delay)
+    }
+}
+
+/// Watches every configured target and backs up those that change, until
+/// SIGTERM or SIGINT. With `poll`, targets are rescanned every `interval`
+/// seconds instead of relying on change notifications. The configuration is
+/// reloaded from `config_path` whenever it changes, and on Unix the service
+/// can be controlled through the socket at `socket`.
+pub fn watch(config_path: PathBuf, opts: &BackupOptions, poll: bool, socket: &Path) -> Result<()> {
+    let shutdown = Shutdown::install()?;
+    let (mut file, mut config, mut repo) = ConfigFile::open(config_path)?;
+    let shared = Arc::new(Shared::new());
+    // Without Unix domain sockets the service still runs, just without `ctl`
+    let _listener = if cfg!(unix) {
+        let listener = control::listen(socket, Arc::clone(&shared), shutdown.clone())?;
+        eprintln!("  Control: {}", socket.display());
+        Some(listener)
+    } else {
+        None
+    };
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)
+        .map_err(|e| anyhow::anyhow!("failed to start file watcher: {e}"))?;
+
+    // Writes into a repository inside a watched source must not trigger
+    // another backup
+    let mut repo_root = Path::new(&repo.location()).canonicalize().ok();
+    let mut targets = start_watching(&config, &mut watcher, poll, &BTreeMap::new());
+    let mut next_refresh = Instant::now() + interval(&config);
+
+    loop {
+        if shutdown.requested() {
+            eprintln!("\n  {} Stopped", colored::Colorize::green("✓"));
+            return Ok(());
+        }
+
+        if let Some((new_config, new_repo)) = file.reload() {
+            for state in targets.values().filter(|t| t.mode == Mode::Events) {
+                state.unwatch(&mut watcher);
+            }
+            config = new_config;
+            repo = new_repo;
+            repo_root = Path::new(&repo.location()).canonicalize().ok();
+            targets = start_watching(&config, &mut watcher, poll, &targets);
+            next_refresh = Instant::now() + interval(&config);
+        }
+
+        // Directories matching a dynamic target's glob come and go
+        if Instant::now() >= next_refresh {
+            if config.backup.values().any(|t| t.is_dynamic()) {
+                refresh_targets(&config, &mut watcher, poll, &mut targets);
+            }
+            next_refresh = Instant::now() + interval(&config);
+        }
+
+        for trigger in shared.take_triggers() {
+            for (_, state) in targets
+                .iter_mut()
+                .filter(|(name, _)| trigger.as_ref().map_or(true, |t| config.selects(t, name)))
+            {
+                state.triggered = true;
+            }
+        }
+        publish(&targets, &shared);
+
+        // While paused, changes accumulate; only triggered backups run
+        let paused = shared.paused();
+        let interval = interval(&config);
+        let now = Instant::now();
+        let due: Vec<String> = targets
+            .iter()
+            .filter(|(_, t)| {
+                t.triggered || (!paused && t.deadline(interval).is_some_and(|d| d <= now))
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        if !due.is_empty() {
+            eprintln!(
+                "\n  {} {}",
+                colored::Colorize::dimmed("───"),
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            );
+            let opts = BackupOptions {
+                skip_unchanged: config.settings.skip_unchanged.unwrap_or(true),
+                cancel: Some(shutdown.flag()),
+                progress: Some(&shared.progress),
+                ..*opts
+            };
+            let names: Vec<&str> = due.iter().map(String::as_str).collect();
+            let run = backup::backup_targets(&config, repo.as_ref(), &names, &opts);
+            if shutdown.requested() {
+                continue;
+            }
+            let results = collect_results(&due, run);
+
+            // Changes made during the backup are still queued and will mark
+            // their targets again below
+            for (name, result) in results {
+                let Some(state) = targets.get_mut(&name) else {
+                    continue;
+                };
+                let retry = state.finish(result.ok, interval);
+                if let Some(status) = shared.status().targets.get_mut(&name) {
+                    status.last = Some(result);
+                }
+                if let Some(delay) = retry {
+                    eprintln!(
+                        "  {} {name}: retrying in {}s",
+                        colored::Colorize::yellow("!"),
+                        delay.as_secs(),
+                    );
+                }
+            }
+            continue;
+        }
+
+        let timeout = targets
+            .values()
+            .filter_map(|t| t.deadline(interval))
+            .min()
+            .map_or(interval, |d| d.saturating_duration_since(now));
+        match rx.recv_timeout(timeout.min(TICK)) {
+            Ok(Ok(event)) => record_event(&mut targets, &event, repo_root.as_deref()),
+            Ok(Err(e)) => {
+                // A watch that can't be extended (e.g. new directories past
+                // the watch limit) would miss changes from now on
+                let affected: Vec<String> = targets
+                    .iter()
+                    .filter(|(_, t)| {
+                        t.mode == Mode::Events
+                            && (e.paths.is_empty() || e.paths.iter().any(|p| t.contains(p)))
+                    })
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                for name in affected {
+                    if let Some(state) = targets.get_mut(&name) {
+                        state.unwatch(&mut watcher);
+                        warn_polling(&name, &e, interval);
+                        state.mode = Mode::Polling;
+                        state.next_poll = Instant::now();
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow::anyhow!("file watcher stopped unexpectedly").into());
+            }
+        }
+    }
+}
+
+/// The outcome of a backup run for each target in `due`.
+fn collect_results(due: &[String], run: Result<backup::BackupRun>) -> BTreeMap<String, LastRun> {
+    let finished_at = Utc::now();
+    let mut results: BTreeMap<String, LastRun> = due
+        .iter()
+        .map(|name| {
+            let result = LastRun {
+                finished_at,
+                ok: true,
+                snapshot: None,
+                error: None,
+            };
+            (name.clone(), result)
+        })
+        .collect();
+    match run {
+        Ok(run) => {
+            for snapshot in run.snapshots {
+                if let Some(result) = results.get_mut(&snapshot.target_name) {
+                    result.snapshot = Some(snapshot.id);
+                }
+            }
+            for (name, e) in run.failed {
+                if let Some(result) = results.get_mut(&name) {
+                    result.ok = false;
+                    result.error = Some(e.to_string());
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("  {} Backup failed: {e}", colored::Colorize::red("✗"));
+            for result in results.values_mut() {
+                result.ok = false;
+                result.error = Some(e.to_string());
+            }
+        }
+    }
+    results
+}
+
+/// Mirrors the targets' state into the status served on the control socket.
+fn publish(targets: &BTreeMap<String, TargetState>, shared: &Shared) {
+    let (now, utc_now) = (Instant::now(), Utc::now());
+    let mut status = shared.status();
+    status.targets.retain(|name, _| targets.contains_key(name));
+    for (name, state) in targets {
+        let entry = status.targets.entry(name.clone()).or_default();
+        entry.mode = match state.mode {
+            Mode::Events => "events",
+            Mode::Polling => "polling",
+        }
+        .to_string();
+        entry.pending = state.triggered || (state.mode == Mode::Events && state.pending.is_some());
+        entry.retry_at = state.retry_at.map(|at| {
+            utc_now
+                + chrono::Duration::from_std(at.saturating_duration_since(now)).unwrap_or_default()
+        });
+    }
+}
+
+fn interval(config: &Config) -> Duration {
+    Duration::from_secs(config.settings.interval.max(1))
+}
+
+/// Starts monitoring every target of `config`, each due for a first backup
+/// to catch up on changes made while nothing was watching. Targets already
+/// in `previous` keep their backoff.
+fn start_watching(
+    config: &Config,
+    watcher: &mut RecommendedWatcher,
+    poll: bool,
+    previous: &BTreeMap<String, TargetState>,
+) -> BTreeMap<String, TargetState> {
+    let interval = interval(config);
+    let mut targets = BTreeMap::new();
+    for (name, target) in &config.expand_targets() {
+        let mut state = watch_target(name, target, watcher, poll, interval);
+        if let Some(old) = previous.get(name) {
+            state.backoff = old.backoff;
+            state.retry_at = old.retry_at;
+        }
+        targets.insert(name.clone(), state);
+    }
+
+    let watched = targets.values().filter(|t| t.mode == Mode::Events).count();
+    eprintln!(
+        "  {} Watching {} target(s) for changes, polling {} every {}s (Ctrl+C to stop)",
+        colored::Colorize::cyan("👁"),
+        watched,
+        targets.len() - watched,
+        interval.as_secs(),
+    );
+    targets
+}
+
+/// Expands dynamic targets again: sub-targets whose directory appeared are
+/// watched and due for a first backup, those whose directory is gone are
+/// dropped.
+fn refresh_targets(
+    config: &Config,
+    watcher: &mut RecommendedWatcher,
+    poll: bool,
+    targets: &mut BTreeMap<String, TargetState>,
+) {
+    let interval = interval(config);
+    let expanded: BTreeMap<String, BackupTarget> = config.expand_targets().into_iter().collect();
+    targets.retain(|name, state| {
+        let keep = expanded.contains_key(name);
+        if !keep {
+            if state.mode == Mode::Events {
+                state.unwatch(watcher);
+            }
+            eprintln!(
+                "  {} {name}: source is gone, no longer watched",
+                colored::Colorize::yellow("!")
+            );
+        }
+        keep
+    });
+    for (name, target) in &expanded {
+        if !targets.contains_key(name) {
+            eprintln!(
+                "  {} {name}: new source, watching",
+                colored::Colorize::cyan("👁")
+            );
+            let state = watch_target(name, target, watcher, poll, interval);
+            targets.insert(name.clone(), state);
+        }
+    }
+}
+
+/// Starts monitoring one target, due for a first backup.
+fn watch_target(
+    name: &str,
+    target: &BackupTarget,
+    watcher: &mut RecommendedWatcher,
+    poll: bool,
+    interval: Duration,
+) -> TargetState {
+    let now = Instant::now();
+    let roots: Vec<PathBuf> = target
+        .from
+        .iter()
+        .map(|source| source.canonicalize().unwrap_or_else(|_| source.clone()))
+        .collect();
+    let watched = (!poll).then(|| {
+        roots
+            .iter()
+            .try_for_each(|root| watcher.watch(root, RecursiveMode::Recursive))
+    });
+    let mode = match watched {
+        None => Mode::Polling,
+        Some(Ok(())) => Mode::Events,
+        Some(Err(e)) => {
+            for root in &roots {
+                let _ = watcher.unwatch(root);
+            }
+            warn_polling(name, &e, interval);
+            Mode::Polling
+        }
+    };
+    TargetState {
+        roots,
+        mode,
+        pending: Some((now, now)),
+        next_poll: now,
+        backoff: Backoff::default(),
+        retry_at: None,
+        triggered: false,
+    }
+}
+
+/// Marks the targets an event touches as changed.
+fn record_event(
+    targets: &mut BTreeMap<String, TargetState>,
+    event: &Event,
+    repo_root: Option<&Path>,
+) {
+    // Reads (including the backup's own) don't change anything
+    if matches!(event.kind, EventKind::Access(_)) {
+        return;
+    }
+    let now = Instant::now();
+
+    // The notification queue overflowed: any watched target may have changed
+    if event.need_rescan() {
+        for state in targets.values_mut().filter(|t| t.mode == Mode::Events) {
+            state.touch(now);
+        }
+        return;
+    }
+
+    for path in &event.paths {
+        if repo_root.is_some_and(|repo| path.starts_with(repo)) {
+            continue;
+        }
+        for state in targets.values_mut() {
+            if state.mode == Mode::Events && state.contains(path) {
+                state.touch(now);
+            }
+        }
+    }
+}
+
+fn warn_polling(name: &str, error: &notify::Error, interval: Duration) {
+    let reason = match error.kind {
+        notify::ErrorKind::MaxFilesWatch => {
+            "watch limit reached; raise fs.inotify.max_user_watches".to_string()
+        }
+        _ => error.to_string(),
+    };
+    eprintln!(
+        "  {} {name}: can't watch for changes ({reason}), polling every {}s",
+        colored::Colorize::yellow("!"),
+        interval.as_secs(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Settings;
+    use notify::event::{AccessKind, CreateKind};
+
+    fn state(root: &str) -> TargetState {
+        TargetState {
+            roots: vec![PathBuf::from(root)],
+            mode: Mode::Events,
+            pending: None,
+            next_poll: Instant::now(),
+            backoff: Backoff::default(),
+            retry_at: None,
+            triggered: false,
+        }
+    }
+
+    #[test]
+    fn events_mark_only_matching_targets() {
+        let mut targets = BTreeMap::new();
+        targets.insert("docs".to_string(), state("/data/docs"));
+        targets.insert("photos".to_string(), state("/data/photos"));
+        let repo = Path::new("/data/docs/.but");
+
+        let read = Event::new(EventKind::Access(AccessKind::Any)).add_path("/data/photos/a".into());
+        record_event(&mut targets, &read, Some(repo));
+        let own_write = Event::new(EventKind::Create(CreateKind::File))
+            .add_path("/data/docs/.but/blobs/ab/cd".into());
+        record_event(&mut targets, &own_write, Some(repo));
+        assert!(targets.values().all(|t| t.pending.is_none()));
+
+        let change =
+            Event::new(EventKind::Create(CreateKind::File)).add_path("/data/photos/new.jpg".into());
+        record_event(&mut targets, &change, Some(repo));
+        assert!(targets["docs"].pending.is_none());
+        assert!(targets["photos"].pending.is_some());
+    }
+
+    #[test]
+    fn debounce_is_capped_by_interval() {
+        let interval = Duration::from_secs(60);
+        let mut target = state("/data");
+        assert_eq!(target.deadline(interval), None);
+
+        let start = Instant::now();
+        target.touch(start);
+        assert_eq!(target.deadline(interval), Some(start + DEBOUNCE));
+
+        // A steady stream of changes postpones the backup, but only so far
+        target.touch(start + Duration::from_secs(59));
+        assert_eq!(target.deadline(interval), Some(start + interval));
+    }
+
+    #[test]
+    fn dynamic_targets_follow_their_glob() {
+        let dir = std::env::temp_dir().join("but-next-test-watch-dynamic");
+        let _ = std::fs::remove_dir_all(&dir);
+        for user in ["alice", "bob"] {
+            std::fs::create_dir_all(dir.join(user)).unwrap();
+        }
+        let config = Config {
+            settings: Settings::default(),
+            backup: BTreeMap::from([(
+                "homes".to_string(),
+                BackupTarget {
+                    from: vec![dir.join("*")],
+                    ..BackupTarget::default()
+                },
+            )]),
+        };
+        let (tx, _rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).unwrap();
+        let mut targets = start_watching(&config, &mut watcher, true, &BTreeMap::new());
+        targets.values_mut().for_each(|t| t.pending = None);
+
+        std::fs::create_dir(dir.join("carol")).unwrap();
+        std::fs::remove_dir(dir.join("alice")).unwrap();
+        refresh_targets(&config, &mut watcher, true, &mut targets);
+        let names: Vec<&str> = targets.keys().map(String::as_str).collect();
+        assert_eq!(names, ["homes/bob", "homes/carol"]);
+        assert!(targets["homes/bob"].pending.is_none());
+        assert!(targets["homes/carol"].pending.is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}